@@ -53,6 +53,7 @@
             boost.all
             soapysdr
             yaml-cpp
+            hdf5
             pkg-config
             gnuradio
             gqrx
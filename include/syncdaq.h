@@ -0,0 +1,556 @@
+#ifndef SDAA_DATA_H
+#define SDAA_DATA_H
+
+#include <cstdarg>
+#include <cstdint>
+#include <cstdlib>
+#include <ostream>
+#include <new>
+
+namespace syncdaq {
+
+constexpr static const uintptr_t N_BYTE_PER_FRAME = 8192;
+
+/// tail_magic written into frames synthesized by `recv_pkt` to fill dropped packets
+constexpr static const uint64_t GAP_TAIL_MAGIC = 16045690984833335023ull;
+
+/// Ports of a board, the size of the per-port arrays of the C structs
+constexpr static const uintptr_t C_MAX_PORTS = 4;
+
+/// Port boards listen on for commands, unless remapped on the way
+constexpr static const uint16_t DEFAULT_CTRL_PORT = 3000;
+
+/// `timeout_ms` waiting as long as it takes
+constexpr static const uint32_t TIMEOUT_INFINITE = UINT32_MAX;
+
+/// 2: frame records keep `head_magic`, `version` and `tail_magic` of the payload
+/// 3: frame data may be compressed as given by `CaptureMeta::compression`, or requantized
+/// 4: frame records carry a checksum, completed files end with a `FrameIndex`
+constexpr static const uint32_t CAPTURE_VERSION = 4;
+
+/// set in `FrameRecord::flags` for frames synthesized by `recv_pkt`
+constexpr static const uint32_t FRAME_SYNTHESIZED = 1;
+
+/// record size in files of `CAPTURE_VERSION`
+constexpr static const uintptr_t FrameRecord_SIZE = 52;
+
+/// i16 components sharing one bit width
+constexpr static const uintptr_t PACK_BLOCK = 256;
+
+constexpr static const uintptr_t VDIF_HEADER_SIZE = 32;
+
+/// optimal 2-bit threshold in units of sigma
+constexpr static const double VDIF_2BIT_THRESHOLD = 0.9816;
+
+/// sigma of the 8-bit output in quantization levels
+constexpr static const double VDIF_8BIT_SIGMA = 20.0;
+
+/// Last reference epoch the 6 bits of the header can hold, in the second half of 2031
+constexpr static const uint32_t VDIF_MAX_REF_EPOCH = 63;
+
+/// Largest seconds from the reference epoch the 30 bits of the header can hold
+constexpr static const uint64_t VDIF_MAX_SECONDS = 1073741823;
+
+/// alignment of buffers, offsets and lengths required by O_DIRECT
+constexpr static const uintptr_t DIRECT_ALIGN = 4096;
+
+/// frames between two `IndexBlock`s
+constexpr static const uint64_t INDEX_BLOCK_FRAMES = 1024;
+
+constexpr static const uintptr_t IndexFooter_SIZE = 32;
+
+/// optimal step of a uniform 16 level quantizer for gaussian noise, in units of sigma
+constexpr static const double REQUANT_4BIT_STEP = 0.3352;
+
+/// Result of the exported functions, details of a failure from `syncdaq_last_error`
+enum class SdrStatus : int32_t {
+  Ok = 0,
+  NullPointer = -1,
+  InvalidArgument = -2,
+  /// a file or socket could not be opened or used
+  Io = -3,
+  /// the board did not reply, or replied with an error
+  Device = -4,
+  /// the receive thread is gone, no more data will come
+  Disconnected = -5,
+  /// a bug in syncdaq, the handle should not be used any further
+  Panic = -6,
+  /// fewer samples than asked for arrived in time
+  Timeout = -7,
+};
+
+/// Layout of the health part of a `QueryReply`, boards answer with one of them
+enum class CHealthKind {
+  Hl = 0,
+  Te = 1,
+  T510 = 2,
+};
+
+/// Handle of a device, from `new_sdr_device`, `new_sdr_device_multi` or `new_file_device`.
+///
+/// A handle may be used from several threads at once, except for `free_sdr_device`, which
+/// must come after every other call on the handle has returned:
+/// - control calls, e.g. `set_mixer_freq`, may run while other threads fetch, they are
+///   sent to the board one at a time;
+/// - fetch calls on different ports run in parallel, those on the same port one after the
+///   other, each handing out consecutive samples;
+/// - `start_stream_callback` waits for the fetch calls in progress, fetch calls made while
+///   a callback is active fail;
+/// - control calls and `get_*` functions may be made from the callback.
+///
+/// The message of `syncdaq_last_error` is kept per thread.
+struct CSdr;
+
+template<typename T = void>
+struct Option;
+
+/// IPv4 socket address, `ip[0]` is the first number of the dotted quad, `port` in host order
+struct CSockAddrV4 {
+  uint8_t ip[4];
+  uint16_t port;
+};
+
+/// State of the stream of a device, see `get_stream_stats`
+struct CStreamStats {
+  /// frames received from the board, or read from the file
+  uint64_t nreceived;
+  /// frames discarded to align the ports
+  uint64_t ndropped;
+  /// zero-filled frames standing in for packets lost on the way
+  uint64_t nsynthesized;
+  /// datagrams ignored for not being a frame
+  uint64_t nrejected;
+  /// frames waiting to be fetched
+  uintptr_t queued;
+  /// frames the queue holds, receiving stalls and the board's packets are lost once
+  /// it is full
+  uintptr_t capacity;
+  /// samples per second over the last second the stream ran, 0 before
+  double smp_rate;
+  /// seconds since the last frame arrived, negative before the first
+  double since_last_frame;
+};
+
+struct CComplex {
+  int16_t re;
+  int16_t im;
+};
+
+struct CComplexF32 {
+  float re;
+  float im;
+};
+
+/// What the samples handed out by a `fetch_data_*_meta` call are made of
+struct CBlockMeta {
+  /// `pkt_cnt` of the frame the first sample is from
+  uint64_t first_pkt_cnt;
+  /// index of the first sample within that frame
+  uintptr_t first_offset;
+  /// frames the samples are from
+  uintptr_t nframes;
+  /// samples zero-filled for packets lost on the way, in total over all gaps
+  uintptr_t nlost;
+  /// index of the first zero-filled sample in the buffer, meaningless if `nlost` is 0
+  uintptr_t first_lost;
+  /// one past the index of the last zero-filled sample, meaningless if `nlost` is 0.
+  ///
+  /// `first_lost..end_lost` is not a range of lost samples when the block spans
+  /// several gaps: it can hold received samples too, and then `nlost` is less than
+  /// `end_lost - first_lost`. Lost frames are always whole, so a caller needing every
+  /// gap can fetch one frame at a time.
+  uintptr_t end_lost;
+  uint32_t port_id;
+  /// `pkt_cnt` jumped or `port_id` changed between two of the frames, e.g. between two
+  /// replayed files
+  bool discontinuous;
+};
+
+/// Called by `start_stream_callback` for every frame, from a thread of syncdaq.
+///
+/// `data` and `meta` are only valid during the call. Returning false stops the calls, as
+/// does `stop_stream_callback`. When the stream ends it is called a last time with `npt`
+/// 0 and both `data` and `meta` null; it is not called that way when stopped.
+using FrameCallback = bool(*)(void *user_data,
+                              const CComplex *data,
+                              uintptr_t npt,
+                              const CBlockMeta *meta);
+
+/// Health of a board, only the fields of `kind` are set
+struct CHealth {
+  CHealthKind kind;
+  uint32_t xgbe_state[C_MAX_PORTS];
+  uint64_t pkt_sent[C_MAX_PORTS];
+  uint32_t volt12_inner;
+  uint32_t volt12_input;
+  uint32_t vcc1v0;
+  uint32_t vcc1v8;
+  uint32_t mgtavtt1v2;
+  uint32_t mgtavtt1v0;
+  uint32_t temperatures[2];
+  uint32_t rfdc_restart_cnt;
+  float temperature;
+  uint32_t nports;
+  uint64_t pkt_cnt1[C_MAX_PORTS];
+  uint64_t axi_frame_cnt1[C_MAX_PORTS];
+  uint64_t pkt_cnt2[C_MAX_PORTS];
+  uint64_t axi_frame_cnt2[C_MAX_PORTS];
+  /// raw words of a `Te` health, `nwords` of them set
+  uint32_t nwords;
+  uint32_t words[32];
+};
+
+/// Decoded `QueryReply`
+struct CQueryInfo {
+  uint32_t fm_ver;
+  uint32_t tick_cnt1;
+  uint32_t tick_cnt2;
+  uint32_t trans_state;
+  uint32_t locked;
+  /// the four lock bits of `locked` are set
+  bool all_locked;
+  /// `tick_cnt2 - tick_cnt1` is the 10M ticks expected
+  bool tick_ok;
+  CHealth health;
+};
+
+/// Addresses of one 10GbE port of a board, the ip is in network order
+struct CXGbeCfg {
+  uint8_t dst_mac[6];
+  uint8_t src_mac[6];
+  uint8_t dst_ip[4];
+  uint8_t src_ip[4];
+  uint16_t dst_port;
+  uint16_t src_port;
+};
+
+extern "C" {
+
+/// Message of the last failed call on the calling thread, empty if none failed.
+///
+/// The string stays valid until the next failing call on the same thread.
+const char *syncdaq_last_error();
+
+/// Parses `"a.b.c.d:port"`, or a host name with a port, into `out`. The port may be left
+/// out for `default_port`, e.g. `DEFAULT_CTRL_PORT`.
+SdrStatus parse_sock_addr(const char *s, uint16_t default_port, CSockAddrV4 *out);
+
+/// Opens a board, sending the commands of `cfg_file`, and starts receiving its payload.
+///
+/// Commands go to `remote_ctrl` from `local_ctrl_port` on all interfaces, the payload is
+/// received on `local_payload`. The handle is stored into `out` and must be released with
+/// `free_sdr_device`.
+SdrStatus new_sdr_device(CSockAddrV4 remote_ctrl,
+                         uint16_t local_ctrl_port,
+                         CSockAddrV4 local_payload,
+                         const char *cfg_file,
+                         CSdr **out);
+
+/// Like `new_sdr_device`, receiving the payload of `nports` ports of the board, each on
+/// its own address of `local_payload`.
+///
+/// The ports are kept aligned: every port hands out the frames of the same `pkt_cnt` in
+/// the same order, frames only some ports received are dropped. Use `fetch_data_*_port`
+/// for all ports alike, a port not fetched from stalls the others once its queue is full.
+///
+/// # Safety
+///
+/// `local_payload` must hold `nports` addresses.
+SdrStatus new_sdr_device_multi(CSockAddrV4 remote_ctrl,
+                               uint16_t local_ctrl_port,
+                               const CSockAddrV4 *local_payload,
+                               uintptr_t nports,
+                               const char *cfg_file,
+                               CSdr **out);
+
+/// Stores the number of payload ports of the device into `nports`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus get_nports(CSdr *csdr, uintptr_t *nports);
+
+/// Fills `stats` with the counts and state of the stream of port `port`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus get_stream_stats_port(CSdr *csdr, uintptr_t port, CStreamStats *stats);
+
+/// Fills `stats` with the counts and state of the stream of all ports together.
+///
+/// The counts and `smp_rate` are summed over the ports, `queued` and `capacity` are those
+/// of the fullest port and `since_last_frame` is of the port that waited longest, negative
+/// until every port had a frame.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus get_stream_stats(CSdr *csdr, CStreamStats *stats);
+
+/// Opens a capture file as a device, for developing without hardware.
+///
+/// `realtime` paces the frames at the recorded sample rate, `looping` replays the file
+/// forever. The handle is stored into `out`.
+SdrStatus new_file_device(const char *path, bool realtime, bool looping, CSdr **out);
+
+/// # Safety
+///
+/// `csdr` must be null or a handle not freed before, with no other call on it in progress.
+SdrStatus free_sdr_device(CSdr *csdr);
+
+/// Same as `set_mixer_freq` with `sync` 0.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus set_lo_freq(CSdr *csdr, double f_lo_mega_hz);
+
+/// Fills `buf` with the next `npt` samples, blocking until they are received.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_16(CSdr *csdr, CComplex *buf, uintptr_t npt);
+
+/// Like `fetch_data_16`, converting the samples to float.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_cf32(CSdr *csdr, CComplexF32 *buf, uintptr_t npt);
+
+/// Like `fetch_data_16`, waiting at most `timeout_ms` for the samples, not at all if 0,
+/// forever if `TIMEOUT_INFINITE`.
+///
+/// The number of samples written to `buf` is stored into `nfetched`, also when returning
+/// `Timeout` because fewer than `npt` arrived in time, or `Disconnected`. A following
+/// call continues right after them.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_16_timeout(CSdr *csdr,
+                                CComplex *buf,
+                                uintptr_t npt,
+                                uint32_t timeout_ms,
+                                uintptr_t *nfetched);
+
+/// Like `fetch_data_16_timeout`, converting the samples to float.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_cf32_timeout(CSdr *csdr,
+                                  CComplexF32 *buf,
+                                  uintptr_t npt,
+                                  uint32_t timeout_ms,
+                                  uintptr_t *nfetched);
+
+/// Like `fetch_data_16_timeout`, also describing the samples in `meta`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_16_meta(CSdr *csdr,
+                             CComplex *buf,
+                             uintptr_t npt,
+                             uint32_t timeout_ms,
+                             uintptr_t *nfetched,
+                             CBlockMeta *meta);
+
+/// Like `fetch_data_cf32_timeout`, also describing the samples in `meta`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_cf32_meta(CSdr *csdr,
+                               CComplexF32 *buf,
+                               uintptr_t npt,
+                               uint32_t timeout_ms,
+                               uintptr_t *nfetched,
+                               CBlockMeta *meta);
+
+/// Like `fetch_data_16_meta`, for port `port` of a device opened with
+/// `new_sdr_device_multi`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_16_port(CSdr *csdr,
+                             uintptr_t port,
+                             CComplex *buf,
+                             uintptr_t npt,
+                             uint32_t timeout_ms,
+                             uintptr_t *nfetched,
+                             CBlockMeta *meta);
+
+/// Like `fetch_data_cf32_meta`, for port `port` of a device opened with
+/// `new_sdr_device_multi`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+SdrStatus fetch_data_cf32_port(CSdr *csdr,
+                               uintptr_t port,
+                               CComplexF32 *buf,
+                               uintptr_t npt,
+                               uint32_t timeout_ms,
+                               uintptr_t *nfetched,
+                               CBlockMeta *meta);
+
+/// Hands every frame received from now on to `cb`, see `FrameCallback`.
+///
+/// The frames of a device with several ports come port after port, as aligned by
+/// `new_sdr_device_multi`.
+///
+/// Waits for the fetch calls in progress, the `fetch_data_*` functions then fail until
+/// `stop_stream_callback` is called. The rest of a frame partly fetched before is handed
+/// out first.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle, `cb` must be safe to call from another thread with
+/// `user_data`.
+SdrStatus start_stream_callback(CSdr *csdr, Option<FrameCallback> cb, void *user_data);
+
+/// Stops the calls started by `start_stream_callback`, returning after the last one.
+///
+/// Must not be called from the callback. Does nothing if no callback was started.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus stop_stream_callback(CSdr *csdr);
+
+/// Samples in a payload frame, the natural block size for the fetch calls
+uintptr_t get_mtu();
+
+/// Starts the board sending payload, does nothing for a replayed file.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus start_data_stream(CSdr *csdr);
+
+/// Sets the frequency of the digital mixer of the board, does nothing for a replayed
+/// file. `freq_mega_hz` must be in (-2000, 2000); it is sent negated, as the board
+/// expects, see `SdrCtrl::set_mixer_freq`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus set_mixer_freq(CSdr *csdr, double freq_mega_hz, uint32_t sync);
+
+/// Stops the board sending payload, does nothing for a replayed file.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus stop_data_stream(CSdr *csdr);
+
+/// Queries the firmware version, clock lock state and health of the board.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus query_device(CSdr *csdr, CQueryInfo *info);
+
+/// Selects the sample clock and PPS sources, storing the resulting clock state into
+/// `clk_state`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus set_clk(CSdr *csdr, uint32_t clk_src, uint32_t pps_src, uint32_t *clk_state);
+
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus set_bit_shift(CSdr *csdr, uint32_t shift_bits);
+
+/// Enables the 10GbE ports whose bits are set in `mask`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus set_port_mask(CSdr *csdr, uint32_t mask);
+
+/// Sets the addresses of 10GbE port `port_id`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+SdrStatus set_xgbe_cfg(CSdr *csdr, uint32_t port_id, const CXGbeCfg *cfg);
+
+/// Reads the addresses of the 10GbE ports, storing up to `max_n` of them into `cfg` and
+/// the number of ports of the board into `nports`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `cfg` hold `max_n` values.
+SdrStatus query_xgbe_cfg(CSdr *csdr, CXGbeCfg *cfg, uintptr_t max_n, uintptr_t *nports);
+
+/// Reads `nbytes` from the I2C device `dev_addr` into `buf`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `nbytes` bytes.
+SdrStatus i2c_read(CSdr *csdr, uint32_t dev_addr, uint8_t *buf, uint32_t nbytes);
+
+/// Reads `nbytes` from register `reg_addr` of the I2C device `dev_addr` into `buf`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `nbytes` bytes.
+SdrStatus i2c_read_reg(CSdr *csdr,
+                       uint32_t dev_addr,
+                       uint32_t reg_addr,
+                       uint8_t *buf,
+                       uint32_t nbytes);
+
+/// Writes `len` bytes of `data` to the I2C device `dev_addr`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `data` hold `len` bytes.
+SdrStatus i2c_write(CSdr *csdr, uint32_t dev_addr, const uint8_t *data, uint32_t len);
+
+/// Writes `len` bytes of `data` to register `reg_addr` of the I2C device `dev_addr`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `data` hold `len` bytes.
+SdrStatus i2c_write_reg(CSdr *csdr,
+                        uint32_t dev_addr,
+                        uint32_t reg_addr,
+                        const uint8_t *data,
+                        uint32_t len);
+
+/// Broadcasts a query to `addr`, storing the addresses of up to `max_n` boards that
+/// replied into `result` and their number into `nfound`.
+///
+/// # Safety
+///
+/// `result` must hold `max_n` values.
+SdrStatus find_device(CSockAddrV4 addr,
+                      CSockAddrV4 *result,
+                      uintptr_t max_n,
+                      uint16_t local_port,
+                      uintptr_t *nfound);
+
+/// Sends `Init` and then `Sync` to the board at `addr` from `local_port`, without a
+/// handle.
+SdrStatus make_device(CSockAddrV4 addr, uint16_t local_port);
+
+/// Sends `StreamStop` to the board at `addr` from `local_port`, without a handle.
+SdrStatus unmake_device(CSockAddrV4 addr, uint16_t local_port);
+
+/// Sends `StreamStart` to the board at `addr` from `local_port`, without a handle.
+SdrStatus start_stream(CSockAddrV4 addr, uint16_t local_port);
+
+}  // extern "C"
+
+}  // namespace syncdaq
+
+#endif  // SDAA_DATA_H
@@ -38,6 +38,7 @@ pkgs.mkShell {
     boost.all
     soapysdr
     yaml-cpp
+    hdf5
     pkg-config
     gnuradio
     gqrx
@@ -0,0 +1,231 @@
+use std::{fs::File, path::Path, sync::Arc};
+
+use crossbeam::channel::{Receiver, Sender};
+use lockfree_object_pool::{LinearObjectPool, LinearOwnedReusable};
+use num::Complex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    payload::{Payload, n_pt_per_frame},
+    utils::as_complex_t,
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BeamCfg {
+    /// port_id written into the frames of this beam
+    pub port_id: u32,
+    /// one [re, im] weight per input, in the order the inputs are given
+    pub weights: Vec<[f32; 2]>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BeamWeights {
+    pub beams: Vec<BeamCfg>,
+}
+
+impl BeamWeights {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let w: Self = serde_yaml::from_reader(File::open(path)?)?;
+        if w.ninputs().is_none() {
+            return Err("beams have different numbers of weights".into());
+        }
+        Ok(w)
+    }
+
+    pub fn ninputs(&self) -> Option<usize> {
+        let n = self.beams.first()?.weights.len();
+        self.beams.iter().all(|b| b.weights.len() == n).then_some(n)
+    }
+}
+
+pub enum BeamCmd {
+    SetWeights(BeamWeights),
+    Destroy,
+}
+
+/// Backward `pkt_cnt` jumps of up to this many frames are late packets, not a restart
+const RESTART_TOLERANCE: u64 = 16;
+
+/// Receives frames of several inputs aligned by `pkt_cnt`.
+///
+/// An input whose `pkt_cnt` jumps backwards started counting over, e.g. after a restart of
+/// the board. The frames of the inputs still counting on from before are dropped until
+/// they start over as well.
+pub struct Aligner {
+    rx: Vec<Receiver<LinearOwnedReusable<Payload>>>,
+    last_cnt: Vec<Option<u64>>,
+}
+
+impl Aligner {
+    pub fn new(rx: Vec<Receiver<LinearOwnedReusable<Payload>>>) -> Self {
+        let last_cnt = vec![None; rx.len()];
+        Self { rx, last_cnt }
+    }
+
+    pub fn ninputs(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// The next frame of input `i`, and whether the input started counting over with it
+    fn next(&mut self, i: usize) -> Option<(LinearOwnedReusable<Payload>, bool)> {
+        let p = self.rx[i].recv().ok()?;
+        let restarted = self.last_cnt[i].is_some_and(|c| p.pkt_cnt + RESTART_TOLERANCE < c);
+        self.last_cnt[i] = Some(p.pkt_cnt);
+        Some((p, restarted))
+    }
+
+    /// Receives one frame from every input until all of them carry the same `pkt_cnt`,
+    /// calling `on_drop` with the index of the input of every frame skipped
+    pub fn recv(
+        &mut self,
+        mut on_drop: impl FnMut(usize),
+    ) -> Option<Vec<LinearOwnedReusable<Payload>>> {
+        let mut frames = Vec::with_capacity(self.rx.len());
+        let mut restarted = Vec::with_capacity(self.rx.len());
+        for i in 0..self.rx.len() {
+            let (p, r) = self.next(i)?;
+            frames.push(p);
+            restarted.push(r);
+        }
+        loop {
+            if restarted.iter().any(|&r| r) {
+                for i in 0..frames.len() {
+                    while !restarted[i] {
+                        let (p, r) = self.next(i)?;
+                        frames[i] = p;
+                        restarted[i] = r;
+                        on_drop(i);
+                    }
+                }
+                restarted.fill(false);
+            }
+
+            let max_cnt = frames.iter().map(|p| p.pkt_cnt).max()?;
+            if frames.iter().all(|p| p.pkt_cnt == max_cnt) {
+                return Some(frames);
+            }
+            'inputs: for i in 0..frames.len() {
+                while frames[i].pkt_cnt < max_cnt {
+                    let (p, r) = self.next(i)?;
+                    frames[i] = p;
+                    on_drop(i);
+                    if r {
+                        restarted[i] = true;
+                        break 'inputs;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct Beamformer {
+    weights: Vec<Vec<Complex<f32>>>,
+    port_ids: Vec<u32>,
+    acc: Vec<Complex<f32>>,
+    pool: Arc<LinearObjectPool<Payload>>,
+}
+
+impl Beamformer {
+    pub fn new(weights: &BeamWeights) -> Self {
+        let mut result = Self {
+            weights: Vec::new(),
+            port_ids: Vec::new(),
+            acc: vec![Complex::default(); n_pt_per_frame::<i16>()],
+            pool: Arc::new(LinearObjectPool::new(Payload::default, |v| {
+                v.pkt_cnt = 0;
+                v.data.fill(0);
+            })),
+        };
+        result.set_weights(weights);
+        result
+    }
+
+    pub fn set_weights(&mut self, weights: &BeamWeights) {
+        self.weights = weights
+            .beams
+            .iter()
+            .map(|b| b.weights.iter().map(|w| Complex::new(w[0], w[1])).collect())
+            .collect();
+        self.port_ids = weights.beams.iter().map(|b| b.port_id).collect();
+    }
+
+    pub fn nbeams(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Forms all beams from one set of aligned frames.
+    ///
+    /// If any input frame is synthesized the beam frames are zero and marked synthesized too.
+    pub fn form(&mut self, inputs: &[&Payload]) -> Vec<LinearOwnedReusable<Payload>> {
+        let any_gap = inputs.iter().any(|p| p.is_synthesized());
+        let mut result = Vec::with_capacity(self.nbeams());
+        for (w, &port_id) in self.weights.iter().zip(&self.port_ids) {
+            assert_eq!(w.len(), inputs.len());
+            let mut out = self.pool.pull_owned();
+            out.copy_header(inputs[0]);
+            out.port_id = port_id;
+            if any_gap {
+                out.mark_synthesized();
+                out.data.fill(0);
+                result.push(out);
+                continue;
+            }
+
+            self.acc.fill(Complex::default());
+            for (&wi, p) in w.iter().zip(inputs) {
+                for (a, x) in self.acc.iter_mut().zip(as_complex_t::<i16>(&p.data)) {
+                    *a += wi * Complex::new(x.re as f32, x.im as f32);
+                }
+            }
+            let dst = unsafe {
+                std::slice::from_raw_parts_mut(
+                    out.data.as_mut_ptr() as *mut Complex<i16>,
+                    n_pt_per_frame::<i16>(),
+                )
+            };
+            for (d, a) in dst.iter_mut().zip(&self.acc) {
+                // `as` saturates, so overflowing beams clip instead of wrapping
+                *d = Complex::new(a.re.round() as i16, a.im.round() as i16);
+            }
+            result.push(out);
+        }
+        result
+    }
+}
+
+pub fn run_beamformer(
+    rx_inputs: Vec<Receiver<LinearOwnedReusable<Payload>>>,
+    tx_beams: Vec<Sender<LinearOwnedReusable<Payload>>>,
+    rx_cmd: Receiver<BeamCmd>,
+    weights: BeamWeights,
+) {
+    let mut bf = Beamformer::new(&weights);
+    let mut aligner = Aligner::new(rx_inputs);
+    assert_eq!(bf.nbeams(), tx_beams.len());
+    loop {
+        while let Ok(cmd) = rx_cmd.try_recv() {
+            match cmd {
+                BeamCmd::SetWeights(w) => {
+                    if w.beams.len() == tx_beams.len() && w.ninputs() == Some(aligner.ninputs()) {
+                        bf.set_weights(&w);
+                        println!("beam weights updated");
+                    } else {
+                        eprintln!("beam weights rejected: shape mismatch");
+                    }
+                }
+                BeamCmd::Destroy => return,
+            }
+        }
+
+        let Some(frames) = aligner.recv(|_| {}) else {
+            return;
+        };
+        let inputs: Vec<&Payload> = frames.iter().map(|p| &**p).collect();
+        for (b, tx) in bf.form(&inputs).into_iter().zip(&tx_beams) {
+            if tx.send(b).is_err() {
+                return;
+            }
+        }
+    }
+}
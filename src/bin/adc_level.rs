@@ -0,0 +1,112 @@
+use std::net::{SocketAddrV4, UdpSocket};
+
+use clap::Parser;
+use crossbeam::channel::bounded;
+use lockfree_object_pool::LinearOwnedReusable;
+use syncdaq::{
+    level::{AutoLevelCfg, PortLevels, auto_level},
+    payload::Payload,
+    pipeline::{RecvCmd, recv_pkt},
+    sdr::SdrCtrl,
+    utils::set_recv_buffer_size,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short = 'a', long = "addr", value_name = "payload ip:port")]
+    local_addr: String,
+
+    #[clap(short = 'r', value_name = "remote ctrl ip:port, enables auto level")]
+    remote_ctrl_addr: Option<SocketAddrV4>,
+
+    #[clap(
+        short = 'L',
+        value_name = "local ctrl ip:port",
+        default_value = "0.0.0.0:3001"
+    )]
+    local_ctrl_addr: SocketAddrV4,
+
+    #[clap(
+        short = 'n',
+        value_name = "nframes per measurement",
+        default_value = "1024"
+    )]
+    nframes: usize,
+
+    #[clap(short = 'b', value_name = "nbins to print", default_value = "32")]
+    nbins: usize,
+
+    #[clap(long = "lo", value_name = "target rms low", default_value = "256")]
+    target_rms_lo: f64,
+
+    #[clap(long = "hi", value_name = "target rms high", default_value = "1024")]
+    target_rms_hi: f64,
+
+    #[clap(long = "init", value_name = "initial shift bits", default_value = "8")]
+    init_shift: u32,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let socket = UdpSocket::bind(&args.local_addr).expect("failed to bind local addr");
+    set_recv_buffer_size(&socket, 1024 * 1024 * 1024).unwrap();
+    let (tx, rx) = bounded::<LinearOwnedReusable<Payload>>(65536);
+    let (tx_cmd, rx_cmd) = bounded(32);
+    let rx_thread = std::thread::spawn(|| recv_pkt(socket.into(), tx, rx_cmd));
+
+    if let Some(remote_ctrl_addr) = args.remote_ctrl_addr {
+        let ctrl = SdrCtrl {
+            remote_ctrl_addr,
+            local_ctrl_addr: args.local_ctrl_addr,
+        };
+        let cfg = AutoLevelCfg {
+            target_rms_lo: args.target_rms_lo,
+            target_rms_hi: args.target_rms_hi,
+            init_shift: args.init_shift,
+            nframes: args.nframes,
+            ..Default::default()
+        };
+        let result = auto_level(&ctrl, &rx, &cfg).expect("failed to set bit shift");
+        for s in &result.steps {
+            println!(
+                "shift_bits: {} rms: {:.1} clip: {:e} eff_bits: {:.2}",
+                s.shift_bits, s.rms, s.clip_fraction, s.effective_bits
+            );
+        }
+        if result.converged {
+            println!("converged: shift_bits={}", result.shift_bits);
+        } else {
+            println!("not converged, last shift_bits={}", result.shift_bits);
+        }
+    } else {
+        let mut levels = PortLevels::default();
+        for _ in 0..args.nframes {
+            let payload = rx.recv().expect("failed to recv payload");
+            levels.accumulate(&payload);
+        }
+        for (port_id, stats) in &levels.ports {
+            println!("port: {port_id}");
+            println!(
+                "rms: {:.1} peak: {} clip: {:e} peak_bits: {} eff_bits: {:.2}",
+                stats.rms(),
+                stats.peak(),
+                stats.clip_fraction(),
+                stats.peak_bits(),
+                stats.effective_bits()
+            );
+            let hist = stats.rebinned(args.nbins);
+            let max = *hist.iter().max().unwrap_or(&1) as f64;
+            for (i, &c) in hist.iter().enumerate() {
+                let lo = i as i64 * 65536 / args.nbins as i64 - 32768;
+                let bar = "#".repeat((c as f64 / max * 60.0).ceil() as usize);
+                println!("{lo:>7} {c:>12} {bar}");
+            }
+        }
+    }
+
+    let _ = tx_cmd.send(RecvCmd::Destroy);
+    drop(rx);
+    let _ = rx_thread.join();
+}
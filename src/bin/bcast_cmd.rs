@@ -26,8 +26,8 @@ struct Args {
     #[clap(
         short = 'd',
         long = "debug",
-        value_name = "debug level",
-        default_value("0")
+        value_name = "debug level, 0 quiet, 2 raw replies",
+        default_value("1")
     )]
     debug_level: u32,
 }
@@ -44,7 +44,8 @@ fn main() {
             &args.local_addr,
             Some(Duration::from_secs(args.timeout)),
             debug_level,
-        );
+        )
+        .expect("failed to send cmd");
 
         println!("replied:");
 
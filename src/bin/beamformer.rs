@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::UdpSocket,
+    time::Duration,
+};
+
+use clap::Parser;
+use crossbeam::channel::{bounded, unbounded};
+use lockfree_object_pool::LinearOwnedReusable;
+use syncdaq::{
+    beamformer::{BeamCmd, BeamWeights, run_beamformer},
+    payload::Payload,
+    pipeline::recv_pkt,
+    utils::{as_u8_slice, set_recv_buffer_size},
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short = 'a', long = "addr", num_args(1..), value_name = "<ip:port> ...")]
+    local_addr: Vec<String>,
+
+    #[clap(short = 'w', value_name = "weights.yaml, reloaded when modified")]
+    weights: String,
+
+    #[clap(
+        short = 'o',
+        long = "out",
+        value_name = "out prefix, one file per beam"
+    )]
+    outname: Option<String>,
+
+    #[clap(short = 'p', value_name = "npkts to dump")]
+    npkts_to_recv: Option<usize>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let weights = BeamWeights::from_file(&args.weights).expect("failed to load weights");
+    assert_eq!(
+        weights.ninputs(),
+        Some(args.local_addr.len()),
+        "number of weights != number of inputs"
+    );
+
+    let mut rx_inputs = Vec::new();
+    for a in &args.local_addr {
+        let socket = UdpSocket::bind(a).expect("failed to bind local addr");
+        set_recv_buffer_size(&socket, 1024 * 1024 * 1024).unwrap();
+        let (tx, rx) = unbounded::<LinearOwnedReusable<Payload>>();
+        let (_tx_cmd, rx_cmd) = unbounded();
+        std::thread::spawn(|| recv_pkt(socket.into(), tx, rx_cmd));
+        rx_inputs.push(rx);
+    }
+
+    let nbeams = weights.beams.len();
+    let (tx_beams, rx_beams): (Vec<_>, Vec<_>) = (0..nbeams)
+        .map(|_| bounded::<LinearOwnedReusable<Payload>>(8192))
+        .unzip();
+    let (tx_bf_cmd, rx_bf_cmd) = bounded(32);
+    let port_ids: Vec<_> = weights.beams.iter().map(|b| b.port_id).collect();
+    std::thread::spawn(|| run_beamformer(rx_inputs, tx_beams, rx_bf_cmd, weights));
+
+    let weights_file = args.weights.clone();
+    std::thread::spawn(move || {
+        let mtime = |f: &str| std::fs::metadata(f).and_then(|m| m.modified()).ok();
+        let mut last = mtime(&weights_file);
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let t = mtime(&weights_file);
+            if t == last {
+                continue;
+            }
+            last = t;
+            match BeamWeights::from_file(&weights_file) {
+                Ok(w) => {
+                    if tx_bf_cmd.send(BeamCmd::SetWeights(w)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => eprintln!("failed to reload weights: {e}"),
+            }
+        }
+    });
+
+    let mut dump_files: Vec<_> = port_ids
+        .iter()
+        .map(|p| {
+            args.outname.as_ref().map(|n| {
+                BufWriter::new(File::create(format!("{n}{p}.dat")).expect("failed to create file"))
+            })
+        })
+        .collect();
+
+    let mut npkts_received = 0;
+    loop {
+        for (rx, f) in rx_beams.iter().zip(&mut dump_files) {
+            let payload = rx.recv().expect("failed to recv beam");
+            if let Some(f) = f.as_mut() {
+                f.write_all(as_u8_slice(&payload.data))
+                    .expect("failed to write");
+            }
+            if payload.pkt_cnt.is_multiple_of(100000) {
+                println!("beam {} cnt: {}", payload.port_id, payload.pkt_cnt);
+            }
+        }
+        npkts_received += 1;
+        if let Some(n) = args.npkts_to_recv
+            && npkts_received >= n
+        {
+            break;
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use std::{fs::File, io::Write, net::UdpSocket};
+use std::net::UdpSocket;
 
 use clap::Parser;
 use syncdaq::{
+    capture_file::{CaptureMetaArgs, FrameSink},
+    compress::Compression,
     payload::Payload,
-    utils::{as_mut_u8_slice, as_u8_slice},
+    utils::as_mut_u8_slice,
 };
 
 #[derive(Parser, Debug)]
@@ -20,14 +22,19 @@ struct Args {
 
     #[clap(short = 'm', value_name = "dumps per npkt", default_value("100000"))]
     dump_per_npkt: usize,
+
+    #[clap(flatten)]
+    meta: CaptureMetaArgs,
 }
 
 fn main() {
     //let (tx,rx)=bounded(256);
     let args = Args::parse();
+    let meta = args.meta.to_meta().expect("failed to load capture meta");
 
     let socket = UdpSocket::bind(&args.local_addr).unwrap();
     let mut payload = Payload::default();
+    let mut gap = Payload::default();
 
     let x = as_mut_u8_slice(&mut payload);
 
@@ -52,17 +59,30 @@ fn main() {
                 && args.npkt_per_dump > 0
                 && let Some(ref outname) = args.outname
             {
-                dump_file = Some(File::create(outname).unwrap());
+                dump_file = Some(FrameSink::create(outname, &meta, args.meta.format).unwrap());
                 npkt_to_dump = args.npkt_per_dump;
                 println!("dump file created");
             }
 
             if let Some(ref mut f) = dump_file {
-                let data = as_u8_slice(&payload.data);
-                f.write_all(data).unwrap();
+                if *c >= payload.pkt_cnt {
+                    f.write_frame(&payload).unwrap();
+                } else {
+                    // dropped packet, recorded as a synthesized frame
+                    gap.copy_header(&payload);
+                    gap.pkt_cnt = *c;
+                    gap.mark_synthesized();
+                    f.write_frame(&gap).unwrap();
+                }
                 npkt_to_dump -= 1;
                 if npkt_to_dump == 0 {
-                    dump_file = None;
+                    if let Some(s) = f.compression_stats()
+                        && (args.meta.compression != Compression::None
+                            || args.meta.requant.bits.is_some())
+                    {
+                        println!("compression ratio {:.2}", s.ratio());
+                    }
+                    dump_file.take().unwrap().finish().unwrap();
                     println!("dump file saved");
                 }
             }
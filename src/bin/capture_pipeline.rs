@@ -1,12 +1,14 @@
 use lockfree_object_pool::LinearOwnedReusable;
-use std::{fs::File, io::Write, net::UdpSocket};
+use std::net::UdpSocket;
 
 use clap::Parser;
 use crossbeam::channel::unbounded;
 use syncdaq::{
+    capture_file::{CaptureMetaArgs, FrameSink},
     payload::Payload,
     pipeline::recv_pkt,
-    utils::{as_u8_slice, set_recv_buffer_size},
+    rotate::{RotateArgs, RotatingSink},
+    utils::set_recv_buffer_size,
 };
 
 #[derive(Parser, Debug)]
@@ -18,15 +20,17 @@ struct Args {
     #[clap(short = 'o', long = "out", value_name = "out name")]
     outname: Option<String>,
 
-    #[clap(short = 'F', value_name = "out prefix for full dump file")]
+    #[clap(
+        short = 'F',
+        value_name = "full dump file prefix, or template with {utc} {port} {device} {seq}"
+    )]
     full_dump_name: Option<String>,
 
     #[clap(
         short = 'k',
-        value_name = "number of pkts per full dump file",
-        default_value = "1000000"
+        value_name = "number of pkts per full dump file, 1000000 without --rotate-*"
     )]
-    npkt_per_full_dump: usize,
+    npkt_per_full_dump: Option<usize>,
 
     #[clap(short = 'n', value_name = "npkts_per_dump", default_value = "100")]
     npkt_per_dump: usize,
@@ -36,11 +40,19 @@ struct Args {
 
     #[clap(short = 'p', value_name = "npkts to dump")]
     npkts_to_recv: Option<usize>,
+
+    #[clap(flatten)]
+    meta: CaptureMetaArgs,
+
+    #[clap(flatten)]
+    rotate: RotateArgs,
 }
 
 fn main() {
     //let (tx,rx)=bounded(256);
     let args = Args::parse();
+    let meta = args.meta.to_meta().expect("failed to load capture meta");
+    let format = args.meta.format;
 
     let socket = UdpSocket::bind(&args.local_addr).expect("failed to bind local addr");
     set_recv_buffer_size(&socket, 10 * 1024 * 1024 * 1024).unwrap();
@@ -54,11 +66,22 @@ fn main() {
     let mut dump_file = None;
 
     let mut old_cnt = None;
-    let mut full_dump_cnt = 0;
     let mut full_dump_file = args.full_dump_name.as_ref().map(|n| {
-        File::create(format!("{}{}.dat", &n, full_dump_cnt)).expect("failed to create file")
+        // a bare prefix keeps the old `{prefix}{n}.dat` naming
+        let template = if n.contains('{') {
+            n.clone()
+        } else {
+            format!("{n}{{seq}}.dat")
+        };
+        let npkt_per_file = match args.npkt_per_full_dump {
+            None if args.rotate.rotate_secs.is_none() && args.rotate.rotate_mega_byte.is_none() => {
+                Some(1000000)
+            }
+            n => n.map(|n| n as u64),
+        };
+        let cfg = args.rotate.to_cfg(&template, npkt_per_file);
+        RotatingSink::new(cfg, &meta, format)
     });
-    let mut npkts_full_dump = 0;
     let mut total_npkts_received = 0;
 
     loop {
@@ -81,54 +104,46 @@ fn main() {
             && args.npkt_per_dump > 0
             && let Some(ref outname) = args.outname
         {
-            dump_file = Some(File::create(outname).expect("failed to create dump file"));
+            dump_file = Some(
+                FrameSink::create(outname, &meta, format).expect("failed to create dump file"),
+            );
             npkt_to_dump = args.npkt_per_dump;
             println!("dump file created");
         }
 
         if let Some(ref mut f) = dump_file {
-            let data = as_u8_slice(&payload.data);
-            f.write_all(data).expect("failed to write");
+            f.write_frame(&payload).expect("failed to write");
             npkt_to_dump -= 1;
             if npkt_to_dump == 0 {
-                dump_file = None;
+                dump_file
+                    .take()
+                    .unwrap()
+                    .finish()
+                    .expect("failed to finish dump file");
                 println!("dump file saved");
 
-                println!(
-                    "pkt_cnt: {}, port_id: {}",
-                    payload.pkt_cnt, payload.port_id
-                );
+                println!("pkt_cnt: {}, port_id: {}", payload.pkt_cnt, payload.port_id);
             }
         }
 
-        if let Some(ref mut f) = full_dump_file {
-            let data = as_u8_slice(&payload.data);
-            f.write_all(data).expect("failed to write");
-            npkts_full_dump += 1;
-
-            if npkts_full_dump == args.npkt_per_full_dump {
-                full_dump_cnt += 1;
-                full_dump_file = args.full_dump_name.as_ref().map(|n| {
-                    File::create(format!("{n}{full_dump_cnt}.dat")).expect("failed to create")
-                });
-                npkts_full_dump = 0;
+        // the stream restarted, so does the numbering of the full dump files
+        if payload.pkt_cnt == 0 {
+            total_npkts_received = 0;
+            if let Some(ref mut f) = full_dump_file {
+                f.close().expect("failed to close");
+                f.set_seq(0);
             }
         }
 
+        if let Some(ref mut f) = full_dump_file {
+            f.write_frame(&payload).expect("failed to write");
+        }
+
         total_npkts_received += 1;
         if let Some(n) = args.npkts_to_recv
             && total_npkts_received == n
         {
             break;
         }
-
-        if payload.pkt_cnt == 0 {
-            full_dump_cnt = 0;
-            npkts_full_dump = 0;
-            total_npkts_received = 0;
-            full_dump_file = args.full_dump_name.as_ref().map(|n| {
-                File::create(format!("{n}{full_dump_cnt}.dat")).expect("failed to create file")
-            });
-        }
     }
 }
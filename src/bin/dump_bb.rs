@@ -1,14 +1,11 @@
 use lockfree_object_pool::LinearOwnedReusable;
-use std::{
-    fs::File,
-    io::{BufWriter, Write},
-    net::UdpSocket,
-};
+use std::{net::UdpSocket, thread::JoinHandle};
 
 use clap::Parser;
-use crossbeam::channel::unbounded;
+use crossbeam::channel::{bounded, unbounded};
 use syncdaq::{
-    payload::Payload,
+    disk_writer::{BehindPolicy, DiskWriter, DiskWriterCfg, DiskWriterStats, stripe_paths},
+    payload::{N_BYTE_PER_FRAME, Payload},
     pipeline::recv_pkt,
     utils::{as_u8_slice, set_recv_buffer_size},
 };
@@ -30,17 +27,65 @@ struct Args {
 
     #[clap(short = 'b', value_name = "buffer size in MB")]
     buffer_size_mega_byte: Option<usize>,
+
+    #[clap(long = "nbufs", value_name = "buffers in flight", default_value = "64")]
+    nbufs: usize,
+
+    #[clap(long = "direct", help = "write with O_DIRECT")]
+    direct: bool,
+
+    #[clap(
+        long = "stripe",
+        num_args(1..),
+        value_name = "<dir> ..., buffers written round robin, one file per dir"
+    )]
+    stripe_dirs: Vec<String>,
+
+    #[clap(
+        long = "drop-when-behind",
+        help = "drop frames instead of queueing them when the disk falls behind"
+    )]
+    drop_when_behind: bool,
+}
+
+fn print_stats(s: &DiskWriterStats) {
+    println!(
+        "disk: {:.1} MB/s write latency mean {:.2} ms max {:.2} ms queued bufs: {} stalls: {} dropped: {} MB",
+        s.throughput() / 1e6,
+        s.mean_latency.as_secs_f64() * 1e3,
+        s.max_latency.as_secs_f64() * 1e3,
+        s.queued,
+        s.nstalls,
+        s.ndropped_bytes / (1024 * 1024)
+    );
+}
+
+fn join_segment(h: JoinHandle<std::io::Result<DiskWriterStats>>) {
+    h.join()
+        .expect("segment finishing thread panicked")
+        .expect("failed to finish file");
 }
 
 fn main() {
     //let (tx,rx)=bounded(256);
     let args = Args::parse();
     let buffer_size_mega_byte = args.buffer_size_mega_byte.unwrap_or(8);
+    let cfg = DiskWriterCfg {
+        buf_size: buffer_size_mega_byte * 1024 * 1024,
+        nbufs: args.nbufs,
+        direct: args.direct,
+        policy: if args.drop_when_behind {
+            BehindPolicy::Drop
+        } else {
+            BehindPolicy::Block
+        },
+    };
 
     let socket = UdpSocket::bind(&args.local_addr).expect("failed to bind local addr");
     set_recv_buffer_size(&socket, 10 * 1024 * 1024 * 1024).unwrap();
-    //let (tx, rx) = bounded::<LinearOwnedReusable<Payload>>(65536);
-    let (tx, rx) = unbounded::<LinearOwnedReusable<Payload>>();
+    // as many frames as the writer buffers, with `BehindPolicy::Block` the receive thread
+    // waits once both are full
+    let (tx, rx) = bounded::<LinearOwnedReusable<Payload>>(cfg.capacity() / N_BYTE_PER_FRAME);
     let (_tx_cmd, rx_cmd) = unbounded();
     //let pool1 = Arc::clone(&pool);
     std::thread::spawn(|| recv_pkt(socket.into(), tx, rx_cmd));
@@ -48,35 +93,43 @@ fn main() {
     let mut npkts_received = 0;
     let mut current_file_no = 0;
     let mut current_file_pkts = 0;
+    let mut behind = false;
+    let mut finishing = Vec::new();
 
-    let mut dump_file = if let Some(ref fname) = args.outname {
-        Some(BufWriter::with_capacity(
-            buffer_size_mega_byte * 1024 * 1024,
-            if args.npkts_per_file.is_some() {
-                File::create(format!("{fname}{current_file_no}.bin"))
-                    .expect("failed to create output file")
-            } else {
-                File::create(fname).expect("failed to create output file")
-            },
-        ))
-    } else {
-        None
+    let create = |name: &str| {
+        DiskWriter::create(&stripe_paths(&args.stripe_dirs, name), cfg.clone())
+            .expect("failed to create output file")
     };
 
+    let mut dump_file = args.outname.as_ref().map(|fname| {
+        if args.npkts_per_file.is_some() {
+            create(&format!("{fname}{current_file_no}.bin"))
+        } else {
+            create(fname)
+        }
+    });
+
     loop {
         let payload = rx.recv().expect("failed to recv payload");
 
         if payload.pkt_cnt % 100000 == 0 {
             println!("cnt: {} queue cnt: {}", payload.pkt_cnt, rx.len());
+            if let Some(f) = dump_file.as_ref() {
+                print_stats(&f.stats());
+            }
         }
 
-        // dump_file.as_mut().map(|f| {
-        //     f.write_all(as_u8_slice(&payload.data)).expect("failed to write to dump file");
-        // });
-
         if let Some(f) = dump_file.as_mut() {
-            f.write_all(as_u8_slice(&payload.data))
+            f.write(as_u8_slice(&payload.data))
                 .expect("failed to write to dump file");
+            if f.is_behind() != behind {
+                behind = !behind;
+                if behind {
+                    eprintln!("disk writer falling behind, {} bufs queued", f.queued());
+                } else {
+                    eprintln!("disk writer caught up");
+                }
+            }
         }
 
         npkts_received += 1;
@@ -95,12 +148,22 @@ fn main() {
         {
             current_file_no += 1;
             current_file_pkts = 0;
-            dump_file = Some(BufWriter::with_capacity(
-                buffer_size_mega_byte * 1024 * 1024,
-                File::create(format!("{fname}{current_file_no}.bin"))
-                    .expect("failed to create output file"),
-            ));
+            let old = dump_file.replace(create(&format!("{fname}{current_file_no}.bin")));
+            // syncing the old file must not hold up the stream
+            let (done, running): (Vec<_>, Vec<_>) = finishing
+                .drain(..)
+                .partition(|h: &JoinHandle<_>| h.is_finished());
+            done.into_iter().for_each(join_segment);
+            finishing = running;
+            if let Some(old) = old {
+                finishing.push(std::thread::spawn(move || old.finish()));
+            }
             println!("new file segment created")
         }
     }
+
+    if let Some(f) = dump_file {
+        print_stats(&f.finish().expect("failed to finish file"));
+    }
+    finishing.into_iter().for_each(join_segment);
 }
@@ -0,0 +1,107 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+use clap::Parser;
+use crossbeam::channel::{bounded, unbounded};
+use syncdaq::{
+    payload::Payload,
+    replay::{FileSource, FileSourceArgs},
+    utils::{as_u8_slice, set_multicast_if_v4, set_send_buffer_size},
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(flatten)]
+    source: FileSourceArgs,
+
+    #[clap(short = 'd', long = "dest", num_args(1..), value_name = "<ip:port> ...")]
+    dest: Vec<SocketAddr>,
+
+    #[clap(
+        short = 'b',
+        long = "bind",
+        value_name = "ip:port",
+        default_value = "0.0.0.0:0"
+    )]
+    bind_addr: String,
+
+    #[clap(
+        long = "fps",
+        value_name = "frames per second, instead of the recorded rate"
+    )]
+    fps: Option<f64>,
+
+    #[clap(long = "ttl", value_name = "multicast ttl", default_value = "1")]
+    ttl: u32,
+
+    #[clap(
+        long = "iface",
+        value_name = "ip of the interface to send multicast from"
+    )]
+    iface: Option<Ipv4Addr>,
+
+    #[clap(
+        long = "send-gaps",
+        help = "also send frames that recv_pkt synthesized for dropped packets"
+    )]
+    send_gaps: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    assert!(!args.dest.is_empty(), "no destination given");
+
+    let mut cfg = args.source.to_cfg();
+    if let Some(fps) = args.fps {
+        cfg.realtime = true;
+        cfg.smp_rate = Some(fps * syncdaq::payload::n_pt_per_frame::<i16>() as f64);
+    }
+    let source = FileSource::open(cfg).expect("failed to open capture files");
+
+    let socket = UdpSocket::bind(&args.bind_addr).expect("failed to bind local addr");
+    set_send_buffer_size(&socket, 64 * 1024 * 1024).unwrap();
+    socket
+        .set_multicast_ttl_v4(args.ttl)
+        .expect("failed to set multicast ttl");
+    if let Some(iface) = args.iface {
+        set_multicast_if_v4(&socket, iface).expect("failed to set multicast interface");
+    }
+
+    let (tx, rx) = bounded(1024);
+    let (_tx_cmd, rx_cmd) = unbounded();
+    let handle = std::thread::spawn(move || source.run(tx, rx_cmd));
+
+    let t0 = Instant::now();
+    let mut nsent = 0_u64;
+    let mut nskipped = 0_u64;
+    for payload in rx {
+        // the board never sent these, a gap is reproduced as a gap
+        if payload.is_synthesized() && !args.send_gaps {
+            nskipped += 1;
+            continue;
+        }
+        let buf = as_u8_slice::<Payload>(&payload);
+        for d in &args.dest {
+            socket.send_to(buf, d).expect("failed to send");
+        }
+        nsent += 1;
+
+        if payload.pkt_cnt % 100000 == 0 {
+            println!(
+                "cnt: {} sent: {} skipped gaps: {} rate: {:.1} frames/s",
+                payload.pkt_cnt,
+                nsent,
+                nskipped,
+                nsent as f64 / t0.elapsed().as_secs_f64()
+            );
+        }
+    }
+    handle
+        .join()
+        .unwrap()
+        .expect("failed to read capture files");
+    println!("{nsent} frames sent, {nskipped} gaps skipped");
+}
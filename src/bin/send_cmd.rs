@@ -26,8 +26,8 @@ struct Args {
     #[clap(
         short = 'd',
         long = "debug",
-        value_name = "debug level",
-        default_value("0")
+        value_name = "debug level, 0 quiet, 2 raw replies",
+        default_value("1")
     )]
     debug_level: u32,
 }
@@ -65,7 +65,7 @@ fn main()->Result<(), Box<dyn std::error::Error>> {
             &args.local_addr,
             Some(Duration::from_secs(args.timeout)),
             debug_level,
-        );
+        )?;
 
         for (_a,msg) in &summary.normal_reply{
             if let ctrl_msg::CtrlMsg::QueryReply { msg_id:_, fm_ver:_, tick_cnt1, tick_cnt2, trans_state:_, locked, health:_ }=msg.clone(){
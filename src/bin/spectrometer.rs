@@ -0,0 +1,142 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::UdpSocket,
+};
+
+use clap::Parser;
+use crossbeam::channel::{bounded, unbounded};
+use lockfree_object_pool::LinearOwnedReusable;
+use syncdaq::{
+    payload::Payload,
+    pipeline::recv_pkt,
+    replay::{FileSource, FileSourceArgs},
+    spectrometer::{SpectrometerCfg, run_spectrometer},
+    utils::{set_recv_buffer_size, slice_as_u8},
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short = 'a', long = "addr", value_name = "ip:port")]
+    local_addr: Option<String>,
+
+    #[clap(flatten)]
+    replay: FileSourceArgs,
+
+    #[clap(short = 'o', long = "out", value_name = "spectra out name, f32")]
+    outname: Option<String>,
+
+    #[clap(short = 'm', value_name = "sk flag mask out name, u8")]
+    mask_name: Option<String>,
+
+    #[clap(short = 'c', value_name = "nch", default_value = "1024")]
+    nch: usize,
+
+    #[clap(
+        short = 'M',
+        value_name = "spectra per sk block",
+        default_value = "256"
+    )]
+    sk_len: usize,
+
+    #[clap(
+        short = 'k',
+        value_name = "sk blocks per integration",
+        default_value = "64"
+    )]
+    nblocks: usize,
+
+    #[clap(short = 's', value_name = "sk threshold in sigma", default_value = "3")]
+    sk_nsigma: f64,
+
+    #[clap(short = 'n', value_name = "number of integrations")]
+    nint: Option<usize>,
+
+    #[cfg(feature = "hdf5")]
+    #[clap(long = "h5", value_name = "hdf5 out name")]
+    h5_name: Option<String>,
+
+    #[cfg(feature = "hdf5")]
+    #[clap(flatten)]
+    meta: syncdaq::capture_file::CaptureMetaArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let cfg = SpectrometerCfg {
+        nch: args.nch,
+        sk_len: args.sk_len,
+        nblocks: args.nblocks,
+        sk_nsigma: args.sk_nsigma,
+    };
+    let (lo, hi) = cfg.sk_thresholds();
+    println!("sk thresholds: [{lo:.4}, {hi:.4}]");
+
+    let (tx, rx) = unbounded::<LinearOwnedReusable<Payload>>();
+    let (_tx_cmd, rx_cmd) = unbounded();
+    if let Some(ref a) = args.local_addr {
+        let socket = UdpSocket::bind(a).expect("failed to bind local addr");
+        set_recv_buffer_size(&socket, 1024 * 1024 * 1024).unwrap();
+        std::thread::spawn(|| recv_pkt(socket.into(), tx, rx_cmd));
+    } else if !args.replay.input.is_empty() {
+        let src = FileSource::open(args.replay.to_cfg()).expect("failed to open input files");
+        std::thread::spawn(|| src.run(tx, rx_cmd).expect("failed to replay"));
+    } else {
+        panic!("either -a or -i must be given");
+    }
+
+    let (tx_spec, rx_spec) = bounded(16);
+    std::thread::spawn(|| run_spectrometer(rx, tx_spec, cfg));
+
+    let mut spec_file = args
+        .outname
+        .as_ref()
+        .map(|n| BufWriter::new(File::create(n).expect("failed to create spectra file")));
+    let mut mask_file = args
+        .mask_name
+        .as_ref()
+        .map(|n| BufWriter::new(File::create(n).expect("failed to create mask file")));
+    #[cfg(feature = "hdf5")]
+    let mut h5_file = args.h5_name.as_ref().map(|n| {
+        let meta = args.meta.to_meta().expect("failed to load cmd files");
+        syncdaq::h5::Hdf5Writer::create(n, &meta).expect("failed to create hdf5 file")
+    });
+
+    for i in 0.. {
+        if let Some(n) = args.nint
+            && i >= n
+        {
+            break;
+        }
+        // the stream only ends when a replay runs out of frames
+        let Ok(s) = rx_spec.recv() else {
+            break;
+        };
+        let nflagged = s.mask.iter().filter(|&&m| m).count();
+        println!(
+            "pkt_cnt: {}..{} valid frames: {}/{} flagged: {:.3}%",
+            s.first_pkt_cnt,
+            s.last_pkt_cnt,
+            s.nvalid_frames,
+            s.nframes,
+            nflagged as f64 / s.mask.len() as f64 * 100.0
+        );
+
+        if let Some(f) = spec_file.as_mut() {
+            let spectrum: Vec<f32> = s.spectrum.iter().map(|&x| x as f32).collect();
+            f.write_all(slice_as_u8(&spectrum))
+                .expect("failed to write spectrum");
+        }
+        if let Some(f) = mask_file.as_mut() {
+            let mask: Vec<u8> = s.mask.iter().map(|&m| m as u8).collect();
+            f.write_all(&mask).expect("failed to write mask");
+        }
+        #[cfg(feature = "hdf5")]
+        if let Some(f) = h5_file.as_mut() {
+            f.write_spectrum(&s).expect("failed to write hdf5");
+            f.flush().expect("failed to flush hdf5");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::net::UdpSocket;
+
+use clap::Parser;
+use crossbeam::channel::unbounded;
+use lockfree_object_pool::LinearOwnedReusable;
+use syncdaq::{
+    capture_file::CaptureMetaArgs,
+    payload::Payload,
+    pipeline::recv_pkt,
+    trigger::{ExternalTrigger, PowerTrigger, ScheduleTrigger, TriggeredCapture},
+    utils::set_recv_buffer_size,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short = 'a', long = "addr", value_name = "ip:port")]
+    local_addr: String,
+
+    #[clap(
+        short = 'o',
+        long = "out",
+        value_name = "out template with {utc} {port} {device} {seq}",
+        default_value = "trig_{utc}_{port}_{seq}.dat"
+    )]
+    template: String,
+
+    #[clap(
+        short = 'K',
+        value_name = "pre-trigger frames",
+        default_value = "100000"
+    )]
+    npre: usize,
+
+    #[clap(
+        short = 'N',
+        value_name = "post-trigger frames",
+        default_value = "100000"
+    )]
+    npost: usize,
+
+    #[clap(long = "power", value_name = "frame power / baseline ratio")]
+    power_ratio: Option<f64>,
+
+    #[clap(long = "every", value_name = "trigger every n pkt_cnt")]
+    period: Option<u64>,
+
+    #[clap(long = "listen", value_name = "ip:port for external triggers")]
+    listen_addr: Option<String>,
+
+    #[clap(short = 'p', value_name = "number of triggers to capture")]
+    ntriggers: Option<usize>,
+
+    #[clap(flatten)]
+    meta: CaptureMetaArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    let meta = args.meta.to_meta().expect("failed to load capture meta");
+
+    let mut capture = TriggeredCapture::new(
+        args.npre,
+        args.npost,
+        &args.template,
+        &meta,
+        args.meta.format,
+    );
+    if let Some(r) = args.power_ratio {
+        capture.add_trigger(Box::new(PowerTrigger::new(r)));
+    }
+    if let Some(period) = args.period {
+        capture.add_trigger(Box::new(ScheduleTrigger { period, offset: 0 }));
+    }
+    if let Some(ref a) = args.listen_addr {
+        capture.add_trigger(Box::new(
+            ExternalTrigger::listen(a).expect("failed to bind trigger addr"),
+        ));
+    }
+
+    let socket = UdpSocket::bind(&args.local_addr).expect("failed to bind local addr");
+    set_recv_buffer_size(&socket, 1024 * 1024 * 1024).unwrap();
+    let (tx, rx) = unbounded::<LinearOwnedReusable<Payload>>();
+    let (_tx_cmd, rx_cmd) = unbounded();
+    std::thread::spawn(|| recv_pkt(socket.into(), tx, rx_cmd));
+
+    let mut ntriggered = 0;
+    loop {
+        let payload = rx.recv().expect("failed to recv payload");
+        if let Some(ev) = capture.feed(payload).expect("failed to write") {
+            println!("triggered at pkt_cnt {}: {}", ev.pkt_cnt, ev.cause);
+            ntriggered += 1;
+        }
+        if let Some(n) = args.ntriggers
+            && ntriggered >= n
+            && !capture.is_dumping()
+        {
+            break;
+        }
+    }
+    capture.close().expect("failed to close");
+}
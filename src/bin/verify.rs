@@ -0,0 +1,103 @@
+use clap::Parser;
+use syncdaq::integrity::{VerifyReport, verify_capture};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(num_args(1..), required = true, value_name = "<capture file> ...")]
+    files: Vec<String>,
+
+    #[clap(long = "yaml", help = "print the full report of every file as YAML")]
+    yaml: bool,
+
+    #[clap(
+        short = 'n',
+        value_name = "max ranges, gaps and corrupt frames listed",
+        default_value = "10"
+    )]
+    nlist: usize,
+}
+
+fn print_report(name: &str, r: &VerifyReport, nlist: usize) {
+    if r.unverified {
+        println!("{name}: UNVERIFIED, not a capture file (raw dump, SigMF or VDIF?)");
+        return;
+    }
+    let status = if r.is_ok() { "OK" } else { "DAMAGED" };
+    println!(
+        "{name}: {status}, version {}, {} frames, {} synthesized in {} gaps, {}{}",
+        r.version,
+        r.nframes,
+        r.nsynthesized,
+        r.gaps.len(),
+        if r.has_checksums {
+            "checksummed"
+        } else {
+            "no checksums"
+        },
+        match (r.has_index, r.index_mismatch) {
+            (false, _) => ", no index (not finished)",
+            (true, false) => ", index ok",
+            (true, true) => ", INDEX MISMATCH",
+        }
+    );
+    if r.truncated_bytes > 0 {
+        println!(
+            "  truncated: {} bytes of incomplete record",
+            r.truncated_bytes
+        );
+    }
+    if r.nbad_records > 0 {
+        println!("  {} unreadable records", r.nbad_records);
+    }
+    if !r.corrupt_frames.is_empty() {
+        println!(
+            "  {} corrupt frames: {:?}{}",
+            r.corrupt_frames.len(),
+            &r.corrupt_frames[..r.corrupt_frames.len().min(nlist)],
+            if r.corrupt_frames.len() > nlist {
+                " ..."
+            } else {
+                ""
+            }
+        );
+    }
+    for (what, runs) in [("range", &r.ranges), ("gap", &r.gaps)] {
+        for x in runs.iter().take(nlist) {
+            println!(
+                "  {what} pkt_cnt {}..={} ({} frames)",
+                x.first,
+                x.last,
+                x.count()
+            );
+        }
+        if runs.len() > nlist {
+            println!("  ... {} more {what}s", runs.len() - nlist);
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut all_ok = true;
+    for f in &args.files {
+        match verify_capture(f) {
+            Ok(r) => {
+                all_ok &= r.is_ok();
+                if args.yaml {
+                    println!("# {f}");
+                    println!("{}", serde_yaml::to_string(&r).unwrap());
+                } else {
+                    print_report(f, &r, args.nlist);
+                }
+            }
+            Err(e) => {
+                all_ok = false;
+                println!("{f}: UNREADABLE, {e}");
+            }
+        }
+    }
+    if !all_ok {
+        std::process::exit(1);
+    }
+}
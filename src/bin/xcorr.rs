@@ -0,0 +1,210 @@
+use std::{fs::File, net::UdpSocket};
+
+use clap::Parser;
+use crossbeam::channel::{bounded, unbounded};
+use lockfree_object_pool::LinearOwnedReusable;
+use num::Complex;
+use syncdaq::{
+    capture_file::CaptureReader,
+    payload::Payload,
+    pipeline::{RecvCmd, recv_pkt},
+    utils::{as_complex_t, set_recv_buffer_size},
+    xcorr::{CrossCorrelator, collect_aligned},
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(
+        short = 'i',
+        long = "in",
+        num_args(2),
+        value_name = "<ref.dat> <other.dat>"
+    )]
+    input: Vec<String>,
+
+    #[clap(
+        short = 'a',
+        long = "addr",
+        num_args(2),
+        value_name = "<ip:port> <ip:port>"
+    )]
+    local_addr: Vec<String>,
+
+    #[clap(short = 'c', value_name = "nch", default_value = "1024")]
+    nch: usize,
+
+    #[clap(short = 'n', value_name = "nframes", default_value = "1024")]
+    nframes: usize,
+
+    #[clap(
+        short = 's',
+        value_name = "frames to skip in files",
+        default_value = "0"
+    )]
+    skip: usize,
+
+    #[clap(short = 'f', value_name = "sample rate in MHz")]
+    smp_rate_mega_hz: Option<f64>,
+
+    #[clap(
+        short = 't',
+        value_name = "delay tolerance in samples",
+        default_value = "0.05"
+    )]
+    delay_tol: f64,
+
+    #[clap(
+        short = 'T',
+        value_name = "phase tolerance in deg",
+        default_value = "5"
+    )]
+    phase_tol_deg: f64,
+
+    #[clap(short = 'p', long = "print-phase")]
+    print_phase: bool,
+
+    #[clap(short = 'w', value_name = "correction.yaml")]
+    correction_file: Option<String>,
+
+    #[cfg(feature = "hdf5")]
+    #[clap(long = "h5", value_name = "hdf5 out name for the visibility")]
+    h5_name: Option<String>,
+
+    #[cfg(feature = "hdf5")]
+    #[clap(flatten)]
+    meta: syncdaq::capture_file::CaptureMetaArgs,
+}
+
+/// Reads `nframes` pairs of frames with matching `pkt_cnt` from two capture files, also
+/// returns the `pkt_cnt` of the first and the last pair
+#[allow(clippy::type_complexity)]
+fn load_frames(
+    fnames: &[String],
+    skip: usize,
+    nframes: usize,
+) -> (Vec<Complex<i16>>, Vec<Complex<i16>>, (u64, u64)) {
+    let mut readers: Vec<_> = fnames
+        .iter()
+        .map(|f| {
+            let mut r = CaptureReader::open(f).expect("failed to open input file");
+            r.seek_frame(skip as u64).expect("failed to seek");
+            r
+        })
+        .collect();
+    let mut px = Payload::default();
+    let mut py = Payload::default();
+    let mut next = |i: usize, p: &mut Payload| {
+        readers[i]
+            .read_frame(p)
+            .expect("failed to read frame")
+            .expect("file too short");
+    };
+    let mut x = Vec::new();
+    let mut y = Vec::new();
+    let mut first_pkt_cnt = None;
+    next(0, &mut px);
+    next(1, &mut py);
+    while x.len() < nframes * px.data.len() / 4 {
+        if px.pkt_cnt < py.pkt_cnt {
+            next(0, &mut px);
+        } else if py.pkt_cnt < px.pkt_cnt {
+            next(1, &mut py);
+        } else {
+            first_pkt_cnt.get_or_insert(px.pkt_cnt);
+            x.extend_from_slice(as_complex_t::<i16>(&px.data));
+            y.extend_from_slice(as_complex_t::<i16>(&py.data));
+            if x.len() < nframes * px.data.len() / 4 {
+                next(0, &mut px);
+                next(1, &mut py);
+            }
+        }
+    }
+    (x, y, (first_pkt_cnt.unwrap_or(px.pkt_cnt), px.pkt_cnt))
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let (x, y, (first_pkt_cnt, last_pkt_cnt)) = if args.input.len() == 2 {
+        load_frames(&args.input, args.skip, args.nframes)
+    } else if args.local_addr.len() == 2 {
+        let mut rx = Vec::new();
+        let mut tx_cmd = Vec::new();
+        for a in &args.local_addr {
+            let socket = UdpSocket::bind(a).expect("failed to bind local addr");
+            set_recv_buffer_size(&socket, 1024 * 1024 * 1024).unwrap();
+            let (tx, rx1) = unbounded::<LinearOwnedReusable<Payload>>();
+            let (tx_cmd1, rx_cmd) = bounded(32);
+            std::thread::spawn(|| recv_pkt(socket.into(), tx, rx_cmd));
+            rx.push(rx1);
+            tx_cmd.push(tx_cmd1);
+        }
+        let result = collect_aligned(&rx[0], &rx[1], args.nframes);
+        for t in tx_cmd {
+            let _ = t.send(RecvCmd::Destroy);
+        }
+        result
+    } else {
+        panic!("either -i or -a must be given");
+    };
+
+    let mut xc = CrossCorrelator::new(args.nch);
+    xc.feed(&x, &y);
+    let est = xc.estimate();
+
+    #[cfg(feature = "hdf5")]
+    if let Some(ref n) = args.h5_name {
+        let meta = args.meta.to_meta().expect("failed to load cmd files");
+        let mut f = syncdaq::h5::Hdf5Writer::create(n, &meta).expect("failed to create hdf5 file");
+        f.write_visibility(first_pkt_cnt, last_pkt_cnt, args.nframes, &xc.visibility())
+            .expect("failed to write hdf5");
+        f.flush().expect("failed to flush hdf5");
+    }
+
+    let df = args.smp_rate_mega_hz.unwrap_or(1.0);
+    let unit = if args.smp_rate_mega_hz.is_some() {
+        "MHz"
+    } else {
+        "fs"
+    };
+
+    if args.print_phase {
+        let mut ch: Vec<_> = (0..est.nch).collect();
+        ch.sort_by(|&a, &b| est.freq[a].total_cmp(&est.freq[b]));
+        println!("# freq({unit}) phase(deg) model(deg) coherence");
+        for k in ch {
+            println!(
+                "{} {} {} {}",
+                est.freq[k] * df,
+                est.phase[k].to_degrees(),
+                est.model_phase(k).to_degrees(),
+                est.coherence[k]
+            );
+        }
+    }
+
+    println!("pkt_cnt: {first_pkt_cnt} to {last_pkt_cnt}");
+    println!("nseg: {} nch: {}", est.nseg, est.nch);
+    println!("int delay: {} smp", est.int_delay);
+    println!("frac delay: {:.4} smp", est.frac_delay);
+    if let Some(fs) = args.smp_rate_mega_hz {
+        println!("total delay: {:.4} ns", est.total_delay() / fs * 1e3);
+    }
+    println!("phase offset: {:.3} deg", est.phase_offset.to_degrees());
+    println!("mean coherence: {:.4}", est.mean_coherence());
+
+    if est.is_aligned(args.delay_tol, args.phase_tol_deg.to_radians()) {
+        println!("aligned");
+    } else {
+        println!(
+            "MISALIGNED: |delay|>{} smp or |phase|>{} deg",
+            args.delay_tol, args.phase_tol_deg
+        );
+    }
+
+    if let Some(ref fname) = args.correction_file {
+        let outfile = File::create(fname).expect("failed to create correction file");
+        serde_yaml::to_writer(outfile, &est.correction()).expect("failed to write correction");
+    }
+}
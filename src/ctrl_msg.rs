@@ -612,26 +612,26 @@ pub struct CmdReplySummary {
     pub normal_reply: Vec<(SocketAddr, CtrlMsg)>,
 }
 
+/// Sends `cmd` to each of `targets` and collects the replies.
+///
+/// Socket errors are returned, targets that did not reply are listed in `no_reply`. Prints
+/// the messages from `debug_level` 1 on and the raw replies from 2 on.
 pub fn send_cmd<A, B>(
     mut cmd: CtrlMsg,
     targets: &[A],
     local_addr: B,
     timeout: Option<Duration>,
     debug_level: u32,
-) -> CmdReplySummary
+) -> std::io::Result<CmdReplySummary>
 where
     A: ToSocketAddrs,
     B: ToSocketAddrs,
 {
-    let socket = UdpSocket::bind(local_addr).expect("faild to bind addr");
-    socket.set_broadcast(true).expect("broadcast set failed");
-    socket
-        .set_nonblocking(true)
-        .expect("nonblocking set failed");
+    let socket = UdpSocket::bind(local_addr)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
 
-    socket
-        .set_read_timeout(timeout)
-        .expect("failed to set timeout");
+    socket.set_read_timeout(timeout)?;
 
     let mut rng1 = rng();
     let mut msg_set = BTreeSet::new();
@@ -641,25 +641,27 @@ where
         let msg_id: u32 = rng1.random();
         cmd.set_msg_id(msg_id);
         msg_set.insert(msg_id);
-        addr_msg_id_map.insert(msg_id, addr.to_socket_addrs().expect("faild to get socket addr").collect::<Vec<_>>());
+        addr_msg_id_map.insert(msg_id, addr.to_socket_addrs()?.collect::<Vec<_>>());
         let mut buf = Cursor::new(Vec::new());
         cmd.write(&mut buf).expect("failed to write cmd to buf");
         let buf = buf.into_inner();
-        socket.send_to(&buf, addr).expect("send error");
+        socket.send_to(&buf, addr)?;
 
-        println!(
-            "{} msg with id={} sent",
-            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-            msg_id,
-        );
-        print_bytes(&buf);
+        if debug_level >= 1 {
+            println!(
+                "{} msg with id={} sent",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                msg_id,
+            );
+            print_bytes(&buf);
 
-        println!("{cmd}");
+            println!("{cmd}");
+        }
 
         let mut buf = vec![0_u8; 9000];
         while let Ok((l, a)) = socket.recv_from(&mut buf) {
             //let (_s, _a)=socket.recv_from(&mut buf).unwrap();
-            if debug_level >= 1 {
+            if debug_level >= 2 {
                 println!(
                     "{} received {} bytes, {} words from {:?}:",
                     Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
@@ -675,37 +677,41 @@ where
 
             let msg_id = reply.get_msg_id();
             if let CtrlMsg::InvalidMsg { .. } = reply {
-                println!(
-                    "{} Invalid msg {:?}",
-                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                    reply
-                );
+                if debug_level >= 1 {
+                    println!(
+                        "{} Invalid msg {:?}",
+                        Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                        reply
+                    );
+                }
                 reply_summary.invalid_reply.push((a, reply));
             } else {
                 reply_summary.normal_reply.push((a, reply));
             }
 
-            println!(
-                "{} msg with id={} replied from {:?}",
-                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                msg_id,
-                a
-            );
+            if debug_level >= 1 {
+                println!(
+                    "{} msg with id={} replied from {:?}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                    msg_id,
+                    a
+                );
+            }
             let x = msg_set.remove(&msg_id);
             assert!(x);
         }
     }
 
-    println!("==waiting for the rest replies==");
-    socket
-        .set_nonblocking(false)
-        .expect("nonblocking set failed");
+    if debug_level >= 1 {
+        println!("==waiting for the rest replies==");
+    }
+    socket.set_nonblocking(false)?;
 
     let mut buf = vec![0_u8; 9000];
 
     if !msg_set.is_empty() {
         while let Ok((l, a)) = socket.recv_from(&mut buf) {
-            if debug_level >= 1 {
+            if debug_level >= 2 {
                 println!(
                     "{} received {} bytes, {} words from {:?}:",
                     Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
@@ -718,27 +724,33 @@ where
 
             let mut cursor = Cursor::new(buf.clone());
             let reply = CtrlMsg::read(&mut cursor).expect("failed to read reply");
-            println!(
-                "{} \n{}",
-                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                reply
-            );
+            if debug_level >= 1 {
+                println!(
+                    "{} \n{}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                    reply
+                );
+            }
 
             let msg_id = reply.get_msg_id();
 
             if let CtrlMsg::InvalidMsg { .. } = reply {
-                println!("Invalid msg received");
+                if debug_level >= 1 {
+                    println!("Invalid msg received");
+                }
                 reply_summary.invalid_reply.push((a, reply));
             } else {
                 reply_summary.normal_reply.push((a, reply));
             }
 
-            println!(
-                "{} msg with id={} replied from {:?}",
-                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                msg_id,
-                a
-            );
+            if debug_level >= 1 {
+                println!(
+                    "{} msg with id={} replied from {:?}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                    msg_id,
+                    a
+                );
+            }
             let x = msg_set.remove(&msg_id);
             assert!(x);
             if msg_set.is_empty() {
@@ -752,30 +764,28 @@ where
         .filter(|&(k, _v)| msg_set.contains(k))
         .map(|(&k, v)| (v.clone(), k))
         .collect();
-    reply_summary
+    Ok(reply_summary)
 }
 
+/// Broadcasts `cmd` to `baddr` and collects the replies until `timeout`, socket errors
+/// are returned. Prints as `send_cmd` does.
 pub fn bcast_cmd<A, B>(
     mut cmd: CtrlMsg,
     baddr: A,
     local_addr: B,
     timeout: Option<Duration>,
     debug_level: u32,
-) -> CmdReplySummary
+) -> std::io::Result<CmdReplySummary>
 where
     A: ToSocketAddrs,
     B: ToSocketAddrs,
 {
     let mut rng1=rng();
-    let socket = UdpSocket::bind(local_addr).expect("failed to bind");
-    socket.set_broadcast(true).expect("broadcast set failed");
-    socket
-        .set_nonblocking(true)
-        .expect("nonblocking set failed");
+    let socket = UdpSocket::bind(local_addr)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
 
-    socket
-        .set_read_timeout(timeout)
-        .expect("failed to set timeout");
+    socket.set_read_timeout(timeout)?;
 
     let mut reply_summary = CmdReplySummary::default();
 
@@ -785,21 +795,23 @@ where
     let mut buf = Cursor::new(Vec::new());
     cmd.write(&mut buf).expect("failed to write cmd to buf");
     let buf = buf.into_inner();
-    socket.send_to(&buf, baddr).expect("send error");
+    socket.send_to(&buf, baddr)?;
 
-    println!(
-        "{} msg with id={} sent",
-        Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-        0,
-    );
-    print_bytes(&buf);
+    if debug_level >= 1 {
+        println!(
+            "{} msg with id={} sent",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            0,
+        );
+        print_bytes(&buf);
 
-    println!("{cmd:?}");
+        println!("{cmd:?}");
+    }
 
     let mut buf = vec![0_u8; 9000];
     while let Ok((l, a)) = socket.recv_from(&mut buf) {
         //let (_s, _a)=socket.recv_from(&mut buf).unwrap();
-        if debug_level >= 1 {
+        if debug_level >= 2 {
             println!(
                 "{} received {} bytes, {} words from {:?}:",
                 Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
@@ -815,33 +827,37 @@ where
 
         let msg_id = reply.get_msg_id();
         if let CtrlMsg::InvalidMsg { .. } = reply {
-            println!(
-                "{} Invalid msg {:?}",
-                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                reply
-            );
+            if debug_level >= 1 {
+                println!(
+                    "{} Invalid msg {:?}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                    reply
+                );
+            }
             reply_summary.invalid_reply.push((a, reply));
         } else {
             reply_summary.normal_reply.push((a, reply));
         }
 
-        println!(
-            "{} msg with id={} replied from {:?}",
-            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-            msg_id,
-            a
-        );
+        if debug_level >= 1 {
+            println!(
+                "{} msg with id={} replied from {:?}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                msg_id,
+                a
+            );
+        }
     }
 
-    println!("==waiting for the rest replies==");
-    socket
-        .set_nonblocking(false)
-        .expect("nonblocking set failed");
+    if debug_level >= 1 {
+        println!("==waiting for the rest replies==");
+    }
+    socket.set_nonblocking(false)?;
 
     let mut buf = vec![0_u8; 9000];
 
     while let Ok((l, a)) = socket.recv_from(&mut buf) {
-        if debug_level >= 1 {
+        if debug_level >= 2 {
             println!(
                 "{} received {} bytes, {} words from {:?}:",
                 Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
@@ -854,27 +870,33 @@ where
 
         let mut cursor = Cursor::new(buf.clone());
         let reply = CtrlMsg::read(&mut cursor).expect("failed to read reply");
-        println!(
-            "{} \n{}",
-            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-            reply
-        );
+        if debug_level >= 1 {
+            println!(
+                "{} \n{}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                reply
+            );
+        }
 
         let msg_id = reply.get_msg_id();
 
         if let CtrlMsg::InvalidMsg { .. } = reply {
-            println!("Invalid msg received");
+            if debug_level >= 1 {
+                println!("Invalid msg received");
+            }
             reply_summary.invalid_reply.push((a, reply));
         } else {
             reply_summary.normal_reply.push((a, reply));
         }
 
-        println!(
-            "{} msg with id={} replied from {:?}",
-            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-            msg_id,
-            a
-        );
+        if debug_level >= 1 {
+            println!(
+                "{} msg with id={} replied from {:?}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                msg_id,
+                a
+            );
+        }
     }
-    reply_summary
+    Ok(reply_summary)
 }
@@ -0,0 +1,443 @@
+use std::{
+    alloc::{Layout, alloc_zeroed, dealloc},
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{Receiver, Sender, bounded, unbounded};
+
+/// alignment of buffers, offsets and lengths required by O_DIRECT
+pub const DIRECT_ALIGN: usize = 4096;
+
+/// A heap buffer aligned to `DIRECT_ALIGN`
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    cap: usize,
+    len: usize,
+}
+
+unsafe impl Send for AlignedBuf {}
+
+impl AlignedBuf {
+    /// `cap` is rounded up to a multiple of `DIRECT_ALIGN`
+    pub fn new(cap: usize) -> Self {
+        let cap = cap.max(1).div_ceil(DIRECT_ALIGN) * DIRECT_ALIGN;
+        let layout = Layout::from_size_align(cap, DIRECT_ALIGN).unwrap();
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) }).expect("failed to allocate");
+        Self { ptr, cap, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.cap
+    }
+
+    /// bytes `push` can still take
+    pub fn room(&self) -> usize {
+        self.cap - self.len
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends as much of `data` as fits, returns the number of bytes taken
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let (len, n) = (self.len, data.len().min(self.cap - self.len));
+        self.as_mut_full()[len..len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        n
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    fn as_mut_full(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.cap) }
+    }
+
+    /// Zero pads the content to a multiple of `DIRECT_ALIGN`
+    fn padded(&mut self) -> &[u8] {
+        let n = self.len.div_ceil(DIRECT_ALIGN) * DIRECT_ALIGN;
+        let len = self.len;
+        self.as_mut_full()[len..n].fill(0);
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), n) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.cap, DIRECT_ALIGN).unwrap();
+        unsafe { dealloc(self.ptr.as_ptr(), layout) };
+    }
+}
+
+/// What `DiskWriter::write` does when all buffers are waiting for the disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BehindPolicy {
+    /// wait for a buffer, pushing the backlog upstream
+    #[default]
+    Block,
+    /// discard whole `write` calls until enough buffers are free for them, counted in
+    /// `ndropped_bytes`
+    Drop,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskWriterCfg {
+    /// rounded up to a multiple of `DIRECT_ALIGN`; `write` calls may span buffers
+    pub buf_size: usize,
+    /// buffers in flight over all stripes
+    pub nbufs: usize,
+    /// open with O_DIRECT, bypassing the page cache
+    pub direct: bool,
+    pub policy: BehindPolicy,
+}
+
+impl Default for DiskWriterCfg {
+    fn default() -> Self {
+        Self {
+            buf_size: 8 * 1024 * 1024,
+            nbufs: 64,
+            direct: false,
+            policy: BehindPolicy::Block,
+        }
+    }
+}
+
+impl DiskWriterCfg {
+    /// bytes all buffers hold together
+    pub fn capacity(&self) -> usize {
+        self.nbufs * self.buf_size
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    bytes_written: AtomicU64,
+    nwrites: AtomicU64,
+    write_ns: AtomicU64,
+    max_write_ns: AtomicU64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskWriterStats {
+    pub bytes_written: u64,
+    pub nwrites: u64,
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+    /// buffers filled but not yet written
+    pub queued: usize,
+    /// times `write` found no free buffer
+    pub nstalls: u64,
+    pub ndropped_bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl DiskWriterStats {
+    /// in bytes per second since the writer was created
+    pub fn throughput(&self) -> f64 {
+        self.bytes_written as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+/// `dir/name` for each dir, or just `name` without dirs
+pub fn stripe_paths<P: AsRef<Path>>(dirs: &[P], name: &str) -> Vec<PathBuf> {
+    if dirs.is_empty() {
+        vec![PathBuf::from(name)]
+    } else {
+        dirs.iter().map(|d| d.as_ref().join(name)).collect()
+    }
+}
+
+fn open_stripe(path: &Path, direct: bool) -> std::io::Result<File> {
+    let mut opts = OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    if direct {
+        opts.custom_flags(libc::O_DIRECT);
+    }
+    opts.open(path)
+}
+
+fn write_stripe(
+    mut file: File,
+    direct: bool,
+    rx: Receiver<AlignedBuf>,
+    tx_free: Sender<AlignedBuf>,
+    counters: Arc<Counters>,
+) -> std::io::Result<()> {
+    let mut pos = 0_u64;
+    for mut buf in rx {
+        let t = Instant::now();
+        let len = buf.len();
+        if direct {
+            // only the last buffer can be partial; it is padded and the file cut back
+            file.write_all(buf.padded())?;
+            if len % DIRECT_ALIGN != 0 {
+                file.set_len(pos + len as u64)?;
+            }
+        } else {
+            file.write_all(buf.as_slice())?;
+        }
+        pos += len as u64;
+
+        let ns = t.elapsed().as_nanos() as u64;
+        counters
+            .bytes_written
+            .fetch_add(len as u64, Ordering::Relaxed);
+        counters.nwrites.fetch_add(1, Ordering::Relaxed);
+        counters.write_ns.fetch_add(ns, Ordering::Relaxed);
+        counters.max_write_ns.fetch_max(ns, Ordering::Relaxed);
+
+        buf.clear();
+        let _ = tx_free.send(buf);
+    }
+    file.sync_data()
+}
+
+/// Writes a byte stream to disk from dedicated threads, one per stripe.
+///
+/// The stream is cut into `buf_size` buffers, buffer `i` goes to stripe `i % nstripes`,
+/// so reading one buffer from each stripe file in turn restores the stream. The caller
+/// only copies into a free buffer; when none is free the writer is falling behind and
+/// `cfg.policy` decides between blocking and dropping.
+pub struct DiskWriter {
+    cfg: DiskWriterCfg,
+    cur: Option<AlignedBuf>,
+    /// free buffers taken by `write` ahead of the bytes going into them
+    spare: Vec<AlignedBuf>,
+    rx_free: Receiver<AlignedBuf>,
+    tx_stripes: Vec<Sender<AlignedBuf>>,
+    handles: Vec<JoinHandle<std::io::Result<()>>>,
+    next_stripe: usize,
+    counters: Arc<Counters>,
+    nstalls: u64,
+    ndropped_bytes: u64,
+    t0: Instant,
+}
+
+impl DiskWriter {
+    pub fn create<P: AsRef<Path>>(paths: &[P], mut cfg: DiskWriterCfg) -> std::io::Result<Self> {
+        assert!(!paths.is_empty());
+        cfg.nbufs = cfg.nbufs.max(paths.len() + 1);
+        let (tx_free, rx_free) = bounded(cfg.nbufs);
+        for _ in 0..cfg.nbufs {
+            tx_free.send(AlignedBuf::new(cfg.buf_size)).unwrap();
+        }
+        let counters = Arc::new(Counters::default());
+
+        let mut tx_stripes = Vec::new();
+        let mut handles = Vec::new();
+        for p in paths {
+            let file = open_stripe(p.as_ref(), cfg.direct)?;
+            let (tx, rx) = unbounded();
+            let (tx_free, counters, direct) = (tx_free.clone(), counters.clone(), cfg.direct);
+            handles.push(std::thread::spawn(move || {
+                write_stripe(file, direct, rx, tx_free, counters)
+            }));
+            tx_stripes.push(tx);
+        }
+
+        Ok(Self {
+            cfg,
+            cur: None,
+            spare: Vec::new(),
+            rx_free,
+            tx_stripes,
+            handles,
+            next_stripe: 0,
+            counters,
+            nstalls: 0,
+            ndropped_bytes: 0,
+            t0: Instant::now(),
+        })
+    }
+
+    fn acquire(&mut self) -> Option<AlignedBuf> {
+        if let Some(b) = self.spare.pop() {
+            return Some(b);
+        }
+        if let Ok(b) = self.rx_free.try_recv() {
+            return Some(b);
+        }
+        self.nstalls += 1;
+        match self.cfg.policy {
+            BehindPolicy::Block => self.rx_free.recv().ok(),
+            BehindPolicy::Drop => None,
+        }
+    }
+
+    fn submit(&mut self, buf: AlignedBuf) -> std::io::Result<()> {
+        let tx = &self.tx_stripes[self.next_stripe];
+        self.next_stripe = (self.next_stripe + 1) % self.tx_stripes.len();
+        tx.send(buf)
+            .map_err(|_| std::io::Error::other("disk writer thread failed"))
+    }
+
+    /// Takes the free buffers `len` bytes need beyond the current one, false if there are
+    /// not enough
+    fn reserve(&mut self, len: usize) -> bool {
+        let room = self.cur.as_ref().map_or(0, AlignedBuf::room);
+        let buf_cap = self.cfg.buf_size.max(1).div_ceil(DIRECT_ALIGN) * DIRECT_ALIGN;
+        let needed = len.saturating_sub(room).div_ceil(buf_cap);
+        while self.spare.len() < needed {
+            match self.rx_free.try_recv() {
+                Ok(b) => self.spare.push(b),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) -> std::io::Result<()> {
+        // a call is dropped whole rather than leaving part of it in the stream
+        if self.cfg.policy == BehindPolicy::Drop && !self.reserve(data.len()) {
+            self.nstalls += 1;
+            self.ndropped_bytes += data.len() as u64;
+            return Ok(());
+        }
+        while !data.is_empty() {
+            if self.cur.is_none() {
+                match self.acquire() {
+                    Some(b) => self.cur = Some(b),
+                    None => {
+                        self.ndropped_bytes += data.len() as u64;
+                        return Ok(());
+                    }
+                }
+            }
+            let buf = self.cur.as_mut().unwrap();
+            let n = buf.push(data);
+            data = &data[n..];
+            if buf.is_full() {
+                let buf = self.cur.take().unwrap();
+                self.submit(buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// buffers filled but not yet written
+    pub fn queued(&self) -> usize {
+        self.cfg.nbufs - self.rx_free.len() - self.spare.len() - self.cur.is_some() as usize
+    }
+
+    /// true when less than a quarter of the buffers are free
+    pub fn is_behind(&self) -> bool {
+        (self.rx_free.len() + self.spare.len()) * 4 < self.cfg.nbufs
+    }
+
+    pub fn stats(&self) -> DiskWriterStats {
+        let nwrites = self.counters.nwrites.load(Ordering::Relaxed);
+        let write_ns = self.counters.write_ns.load(Ordering::Relaxed);
+        DiskWriterStats {
+            bytes_written: self.counters.bytes_written.load(Ordering::Relaxed),
+            nwrites,
+            mean_latency: Duration::from_nanos(write_ns / nwrites.max(1)),
+            max_latency: Duration::from_nanos(self.counters.max_write_ns.load(Ordering::Relaxed)),
+            queued: self.queued(),
+            nstalls: self.nstalls,
+            ndropped_bytes: self.ndropped_bytes,
+            elapsed: self.t0.elapsed(),
+        }
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        if let Some(buf) = self.cur.take()
+            && !buf.is_empty()
+        {
+            self.submit(buf)?;
+        }
+        self.tx_stripes.clear();
+        for h in self.handles.drain(..) {
+            h.join()
+                .map_err(|_| std::io::Error::other("disk writer thread panicked"))??;
+        }
+        Ok(())
+    }
+
+    /// Writes out the partial buffer and waits for all stripes to be synced
+    pub fn finish(mut self) -> std::io::Result<DiskWriterStats> {
+        self.close()?;
+        Ok(self.stats())
+    }
+}
+
+impl Drop for DiskWriter {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, io::Read, os::unix::ffi::OsStrExt};
+
+    use super::*;
+
+    #[test]
+    fn drop_policy_drops_whole_writes() {
+        // a fifo nobody reads until all writes are done stalls the stripe
+        let path = std::env::temp_dir().join(format!("syncdaq-{}-stalled", std::process::id()));
+        let cpath = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) }, 0);
+        let (tx_done, rx_done) = bounded::<()>(1);
+        let reader = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let mut f = File::open(path).unwrap();
+                rx_done.recv().unwrap();
+                let mut out = Vec::new();
+                f.read_to_end(&mut out).unwrap();
+                out
+            })
+        };
+
+        // writes span buffers, which are not a multiple of them
+        const NBYTES: usize = 5000;
+        const NCALLS: usize = 200;
+        let cfg = DiskWriterCfg {
+            buf_size: 3 * DIRECT_ALIGN,
+            nbufs: 4,
+            direct: false,
+            policy: BehindPolicy::Drop,
+        };
+        let mut w = DiskWriter::create(&[&path], cfg).unwrap();
+        for i in 0..NCALLS {
+            w.write(&[i as u8; NBYTES]).unwrap();
+        }
+        let dropped = w.stats().ndropped_bytes as usize;
+        tx_done.send(()).unwrap();
+        // syncing a fifo fails, what was written is still there
+        let _ = w.finish();
+        let out = reader.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(dropped > 0);
+        assert_eq!(dropped % NBYTES, 0);
+        assert_eq!(out.len() + dropped, NBYTES * NCALLS);
+        let mut last = None;
+        for call in out.chunks(NBYTES) {
+            assert!(call.iter().all(|&b| b == call[0]));
+            assert!(last.is_none_or(|l| call[0] > l));
+            last = Some(call[0]);
+        }
+    }
+}
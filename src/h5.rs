@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, path::Path};
+
+use hdf5::{Dataset, File, Group, H5Type, Location, types::VarLenUnicode};
+use num::Complex;
+
+use crate::{capture_file::CaptureMeta, spectrometer::IntegratedSpectrum};
+
+fn attr<T: H5Type>(loc: &Location, name: &str, v: &T) -> hdf5::Result<()> {
+    loc.new_attr::<T>().create(name)?.write_scalar(v)
+}
+
+fn attr_str(loc: &Location, name: &str, v: &str) -> hdf5::Result<()> {
+    let v: VarLenUnicode = v.parse().unwrap_or_default();
+    attr(loc, name, &v)
+}
+
+/// Dataset of shape `(0.., row_shape...)` chunked by one row
+fn new_rows<T: H5Type>(group: &Group, name: &str, row_shape: &[usize]) -> hdf5::Result<Dataset> {
+    match *row_shape {
+        [] => group.new_dataset::<T>().chunk(1024).shape(0..).create(name),
+        [a] => group
+            .new_dataset::<T>()
+            .chunk((1, a))
+            .shape((0.., a))
+            .create(name),
+        [a, b] => group
+            .new_dataset::<T>()
+            .chunk((1, a, b))
+            .shape((0.., a, b))
+            .create(name),
+        _ => Err(format!("{name}: rows of rank {} are not supported", row_shape.len()).into()),
+    }
+}
+
+/// Writes `row` as row `n` of `ds`, growing the first dimension to `n + 1`
+fn write_row<T: H5Type>(ds: &Dataset, n: usize, row: &[T]) -> hdf5::Result<()> {
+    let mut shape = ds.shape();
+    shape[0] = n + 1;
+    ds.resize(shape.clone())?;
+    match shape.len() {
+        1 => ds.write_slice(row, n..n + 1),
+        2 => ds.write_slice(row, (n, ..)),
+        _ => {
+            for (i, r) in row.chunks(shape[2]).enumerate() {
+                ds.write_slice(r, (n, i, ..))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Per-integration bookkeeping shared by all products
+struct Integrations {
+    n: usize,
+    pkt_cnt: Dataset,
+    nframes: Dataset,
+    nvalid_frames: Dataset,
+    utc: Dataset,
+}
+
+impl Integrations {
+    fn new(group: &Group) -> hdf5::Result<Self> {
+        Ok(Self {
+            n: 0,
+            pkt_cnt: new_rows::<u64>(group, "pkt_cnt", &[2])?,
+            nframes: new_rows::<u32>(group, "nframes", &[])?,
+            nvalid_frames: new_rows::<u32>(group, "nvalid_frames", &[])?,
+            utc: new_rows::<f64>(group, "utc", &[])?,
+        })
+    }
+
+    fn write(
+        &mut self,
+        meta: &CaptureMeta,
+        first_pkt_cnt: u64,
+        last_pkt_cnt: u64,
+        nframes: usize,
+        nvalid_frames: usize,
+    ) -> hdf5::Result<()> {
+        let n = self.n;
+        let utc = meta.pkt_cnt_to_unix(first_pkt_cnt).unwrap_or(f64::NAN);
+        write_row(&self.pkt_cnt, n, &[first_pkt_cnt, last_pkt_cnt])?;
+        write_row(&self.nframes, n, &[nframes as u32])?;
+        write_row(&self.nvalid_frames, n, &[nvalid_frames as u32])?;
+        write_row(&self.utc, n, &[utc])?;
+        self.n += 1;
+        Ok(())
+    }
+}
+
+struct SpectraGroup {
+    rows: Integrations,
+    spectrum: Dataset,
+    nvalid: Dataset,
+    sk_mask: Dataset,
+}
+
+struct VisGroup {
+    rows: Integrations,
+    vis_re: Dataset,
+    vis_im: Dataset,
+}
+
+/// Archives integrated products into extendable HDF5 datasets.
+///
+/// The file root carries the `CaptureMeta` as attributes. Spectra of port `p` go
+/// to the group `/spectra/<p>`, visibilities to `/visibility`. Every group has one
+/// row per integration in `pkt_cnt` (first, last), `nframes`, `nvalid_frames` and
+/// `utc` (unix seconds of the first frame, NaN without `--epoch` and `--smp-rate`).
+pub struct Hdf5Writer {
+    file: File,
+    meta: CaptureMeta,
+    spectra: BTreeMap<u32, SpectraGroup>,
+    vis: Option<VisGroup>,
+}
+
+impl Hdf5Writer {
+    pub fn create<P: AsRef<Path>>(path: P, meta: &CaptureMeta) -> hdf5::Result<Self> {
+        let file = File::create(path)?;
+        attr_str(&file, "recorder", "syncdaq")?;
+        attr_str(
+            &file,
+            "meta",
+            &serde_yaml::to_string(meta).unwrap_or_default(),
+        )?;
+        if let Some(ref d) = meta.device {
+            attr_str(&file, "device", d)?;
+        }
+        if let Some(f) = meta.mixer_freq {
+            attr(&file, "mixer_freq", &f)?;
+        }
+        if let Some(p) = meta.mixer_phase {
+            attr(&file, "mixer_phase", &p)?;
+        }
+        if let Some(b) = meta.bit_shift {
+            attr(&file, "bit_shift", &b)?;
+        }
+        if let Some(fs) = meta.smp_rate {
+            attr(&file, "smp_rate", &fs)?;
+        }
+        if let Some(ref t) = meta.pkt_cnt0_utc {
+            attr_str(&file, "pkt_cnt0_utc", t)?;
+        }
+        if let Some(ref t) = meta.start_utc {
+            attr_str(&file, "start_utc", t)?;
+        }
+        Ok(Self {
+            file,
+            meta: meta.clone(),
+            spectra: BTreeMap::new(),
+            vis: None,
+        })
+    }
+
+    pub fn write_spectrum(&mut self, s: &IntegratedSpectrum) -> hdf5::Result<()> {
+        if !self.spectra.contains_key(&s.port_id) {
+            let group = self
+                .file
+                .group("spectra")
+                .or_else(|_| self.file.create_group("spectra"))?
+                .create_group(&s.port_id.to_string())?;
+            attr(&group, "nch", &(s.nch as u32))?;
+            attr(&group, "nblocks", &(s.nblocks as u32))?;
+            attr_str(&group, "channel_order", "fftshift")?;
+            let g = SpectraGroup {
+                rows: Integrations::new(&group)?,
+                spectrum: new_rows::<f32>(&group, "spectrum", &[s.nch])?,
+                nvalid: new_rows::<u32>(&group, "nvalid", &[s.nch])?,
+                sk_mask: new_rows::<u8>(&group, "sk_mask", &[s.nblocks, s.nch])?,
+            };
+            self.spectra.insert(s.port_id, g);
+        }
+        let g = self.spectra.get_mut(&s.port_id).unwrap();
+        let n = g.rows.n;
+        let spectrum: Vec<f32> = s.spectrum.iter().map(|&x| x as f32).collect();
+        let mask: Vec<u8> = s.mask.iter().map(|&m| m as u8).collect();
+        write_row(&g.spectrum, n, &spectrum)?;
+        write_row(&g.nvalid, n, &s.nvalid)?;
+        write_row(&g.sk_mask, n, &mask)?;
+        g.rows.write(
+            &self.meta,
+            s.first_pkt_cnt,
+            s.last_pkt_cnt,
+            s.nframes,
+            s.nvalid_frames,
+        )
+    }
+
+    /// `vis` in fft order, as returned by `CrossCorrelator::visibility`
+    pub fn write_visibility(
+        &mut self,
+        first_pkt_cnt: u64,
+        last_pkt_cnt: u64,
+        nvalid_frames: usize,
+        vis: &[Complex<f64>],
+    ) -> hdf5::Result<()> {
+        if self.vis.is_none() {
+            let group = self.file.create_group("visibility")?;
+            attr(&group, "nch", &(vis.len() as u32))?;
+            attr_str(&group, "channel_order", "fft")?;
+            self.vis = Some(VisGroup {
+                rows: Integrations::new(&group)?,
+                vis_re: new_rows::<f32>(&group, "vis_re", &[vis.len()])?,
+                vis_im: new_rows::<f32>(&group, "vis_im", &[vis.len()])?,
+            });
+        }
+        let g = self.vis.as_mut().unwrap();
+        let n = g.rows.n;
+        let re: Vec<f32> = vis.iter().map(|v| v.re as f32).collect();
+        let im: Vec<f32> = vis.iter().map(|v| v.im as f32).collect();
+        write_row(&g.vis_re, n, &re)?;
+        write_row(&g.vis_im, n, &im)?;
+        let nframes = (last_pkt_cnt + 1 - first_pkt_cnt) as usize;
+        g.rows.write(
+            &self.meta,
+            first_pkt_cnt,
+            last_pkt_cnt,
+            nframes,
+            nvalid_frames,
+        )
+    }
+
+    pub fn flush(&self) -> hdf5::Result<()> {
+        self.file.flush()
+    }
+}
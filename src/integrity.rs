@@ -0,0 +1,526 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use binrw::{BinRead, BinWrite, binrw};
+use serde::Serialize;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{
+    capture_file::{CAPTURE_MAGIC, CaptureMeta, FileHeader, FrameRecord, invalid_data},
+    compress::Compression,
+    payload::N_BYTE_PER_FRAME,
+    requant::requant_frame_len,
+};
+
+/// frames between two `IndexBlock`s
+pub const INDEX_BLOCK_FRAMES: u64 = 1024;
+
+/// xxh3 of a serialized record without its checksum field, followed by the stored data
+pub fn record_checksum(rec_bytes: &[u8], data: &[u8]) -> u64 {
+    let mut h = Xxh3::new();
+    h.update(&rec_bytes[..rec_bytes.len() - 8]);
+    h.update(data);
+    h.digest()
+}
+
+/// inclusive range of `pkt_cnt`
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Copy, Serialize, Debug, PartialEq, Eq)]
+pub struct PktRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl PktRange {
+    /// number of `pkt_cnt` in the range
+    pub fn count(&self) -> u64 {
+        self.last - self.first + 1
+    }
+}
+
+/// file offset of the record of frame `frame_no`
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Copy, Debug)]
+pub struct IndexBlock {
+    pub frame_no: u64,
+    pub offset: u64,
+}
+
+/// Appended by `CaptureWriter` when a file is completed.
+///
+/// `ranges` are the runs of consecutive `pkt_cnt`, `gaps` the runs of frames synthesized
+/// by `recv_pkt` within them.
+#[binrw]
+#[brw(little, magic = b"IDX\0")]
+#[derive(Clone, Debug, Default)]
+pub struct FrameIndex {
+    pub nframes: u64,
+    pub nsynthesized: u64,
+    #[bw(calc = ranges.len() as u64)]
+    nranges: u64,
+    #[br(count = nranges)]
+    pub ranges: Vec<PktRange>,
+    #[bw(calc = gaps.len() as u64)]
+    ngaps: u64,
+    #[br(count = ngaps)]
+    pub gaps: Vec<PktRange>,
+    #[bw(calc = blocks.len() as u64)]
+    nblocks: u64,
+    #[br(count = nblocks)]
+    pub blocks: Vec<IndexBlock>,
+}
+
+impl FrameIndex {
+    /// Accounts for the next frame, whose record starts at `offset`
+    pub fn push(&mut self, rec: &FrameRecord, offset: u64) {
+        if self.nframes.is_multiple_of(INDEX_BLOCK_FRAMES) {
+            self.blocks.push(IndexBlock {
+                frame_no: self.nframes,
+                offset,
+            });
+        }
+        self.nframes += 1;
+
+        let push_run = |runs: &mut Vec<PktRange>| match runs.last_mut() {
+            Some(r) if r.last + 1 == rec.pkt_cnt => r.last = rec.pkt_cnt,
+            _ => runs.push(PktRange {
+                first: rec.pkt_cnt,
+                last: rec.pkt_cnt,
+            }),
+        };
+        push_run(&mut self.ranges);
+        if rec.is_synthesized() {
+            self.nsynthesized += 1;
+            push_run(&mut self.gaps);
+        }
+    }
+
+    /// the block to start from to reach frame `n`
+    pub fn block_of(&self, n: u64) -> Option<&IndexBlock> {
+        let i = self.blocks.partition_point(|b| b.frame_no <= n);
+        self.blocks.get(i.checked_sub(1)?)
+    }
+}
+
+/// Last bytes of a file with an index
+#[binrw]
+#[brw(little, magic = b"SDAQIDX\0")]
+#[derive(Clone, Copy, Debug)]
+pub struct IndexFooter {
+    pub offset: u64,
+    pub len: u64,
+    /// xxh3 of the serialized `FrameIndex`
+    pub checksum: u64,
+}
+
+impl IndexFooter {
+    pub const SIZE: usize = 32;
+}
+
+/// Serializes `index` with its footer
+pub fn index_bytes(index: &FrameIndex, offset: u64) -> std::io::Result<Vec<u8>> {
+    let mut buf = std::io::Cursor::new(Vec::new());
+    index
+        .write(&mut buf)
+        .map_err(|e| invalid_data(e.to_string()))?;
+    let len = buf.get_ref().len() as u64;
+    let footer = IndexFooter {
+        offset,
+        len,
+        checksum: xxhash_rust::xxh3::xxh3_64(buf.get_ref()),
+    };
+    footer
+        .write(&mut buf)
+        .map_err(|e| invalid_data(e.to_string()))?;
+    Ok(buf.into_inner())
+}
+
+/// Reads the footer at the end of `r`, `None` if there is none
+pub fn read_footer<R: Read + Seek>(r: &mut R) -> std::io::Result<Option<IndexFooter>> {
+    let end = r.seek(SeekFrom::End(0))?;
+    if end < IndexFooter::SIZE as u64 {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Start(end - IndexFooter::SIZE as u64))?;
+    let mut buf = [0_u8; IndexFooter::SIZE];
+    r.read_exact(&mut buf)?;
+    let footer = IndexFooter::read(&mut std::io::Cursor::new(&buf)).ok();
+    Ok(footer.filter(|f| f.offset + f.len + IndexFooter::SIZE as u64 == end))
+}
+
+/// Reads the index `footer` points to, checking its checksum
+pub fn read_index<R: Read + Seek>(r: &mut R, footer: &IndexFooter) -> std::io::Result<FrameIndex> {
+    r.seek(SeekFrom::Start(footer.offset))?;
+    let mut buf = vec![0_u8; footer.len as usize];
+    r.read_exact(&mut buf)?;
+    if xxhash_rust::xxh3::xxh3_64(&buf) != footer.checksum {
+        return Err(invalid_data("index checksum mismatch"));
+    }
+    FrameIndex::read(&mut std::io::Cursor::new(&buf)).map_err(|e| invalid_data(e.to_string()))
+}
+
+/// What `verify_capture` found
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct VerifyReport {
+    pub version: u32,
+    /// no capture file header: a raw dump, SigMF or VDIF data, none of which is checked
+    pub unverified: bool,
+    /// frame records carry checksums, from version 4 on
+    pub has_checksums: bool,
+    pub has_index: bool,
+    /// index present but unreadable, or not matching the frames
+    pub index_mismatch: bool,
+    pub nframes: u64,
+    pub nsynthesized: u64,
+    /// frame numbers, in file order, with bad checksums, or lost with a bad record
+    pub corrupt_frames: Vec<u64>,
+    /// records whose header could not be parsed, skipped using the index where possible
+    pub nbad_records: u64,
+    /// bytes at the end of the frame data that do not form a complete record
+    pub truncated_bytes: u64,
+    pub ranges: Vec<PktRange>,
+    pub gaps: Vec<PktRange>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        !self.unverified
+            && self.corrupt_frames.is_empty()
+            && self.nbad_records == 0
+            && self.truncated_bytes == 0
+            && !self.index_mismatch
+    }
+}
+
+/// length of the frame data of `rec`, `None` if it can not be right
+fn stored_len(meta: &CaptureMeta, rec: &FrameRecord) -> Option<usize> {
+    let bits = meta.sample_format.bits_per_component() as u32;
+    let len = rec.len as usize;
+    let ok = if bits < 16 {
+        len == requant_frame_len(bits)
+    } else if meta.compression == Compression::None {
+        len == N_BYTE_PER_FRAME
+    } else {
+        len < 2 * N_BYTE_PER_FRAME
+    };
+    ok.then_some(len)
+}
+
+/// Walks every record of a capture file, checking lengths and checksums, and compares the
+/// result with the trailing index if there is one.
+///
+/// Only an unreadable file header is an error; damage further in is reported. Files
+/// without the capture magic are reported as `unverified`, never as ok.
+pub fn verify_capture<P: AsRef<Path>>(path: P) -> std::io::Result<VerifyReport> {
+    let mut f = BufReader::new(File::open(path)?);
+    let file_len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(0))?;
+    let mut report = VerifyReport::default();
+
+    let mut magic = [0_u8; 8];
+    if file_len >= 8 {
+        f.read_exact(&mut magic)?;
+    }
+    if magic != CAPTURE_MAGIC {
+        report.unverified = true;
+        return Ok(report);
+    }
+    f.seek(SeekFrom::Start(0))?;
+    let header = FileHeader::read(&mut f).map_err(|e| invalid_data(e.to_string()))?;
+    let meta: CaptureMeta = serde_yaml::from_slice(&header.meta).map_err(invalid_data)?;
+    report.version = header.version;
+    report.has_checksums = header.version >= 4;
+    let data_offset = f.stream_position()?;
+
+    let footer = read_footer(&mut f)?;
+    report.has_index = footer.is_some();
+    let index = match footer {
+        Some(ref ft) => match read_index(&mut f, ft) {
+            Ok(idx) => Some(idx),
+            Err(_) => {
+                report.index_mismatch = true;
+                None
+            }
+        },
+        None => None,
+    };
+    let data_end = footer.map_or(file_len, |ft| ft.offset);
+
+    let rec_size = FrameRecord::size(header.version);
+    let mut found = FrameIndex::default();
+    let mut rec_buf = vec![0_u8; rec_size];
+    let mut data = Vec::new();
+    let mut pos = data_offset;
+    f.seek(SeekFrom::Start(pos))?;
+    while pos < data_end {
+        if data_end - pos < rec_size as u64 {
+            report.truncated_bytes = data_end - pos;
+            break;
+        }
+        f.read_exact(&mut rec_buf)?;
+        let rec = FrameRecord::read_args(&mut std::io::Cursor::new(&rec_buf), (header.version,))
+            .ok()
+            .and_then(|r| stored_len(&meta, &r).map(|n| (r, n)));
+        let Some((rec, len)) = rec else {
+            // lost track of the records, continue at the next indexed block if any
+            report.nbad_records += 1;
+            let next = index.as_ref().and_then(|idx| {
+                idx.blocks
+                    .iter()
+                    .find(|b| b.offset > pos)
+                    .map(|b| (b.offset, b.frame_no))
+            });
+            match next {
+                Some((offset, frame_no)) => {
+                    report.corrupt_frames.extend(found.nframes..frame_no);
+                    found.nframes = frame_no;
+                    pos = f.seek(SeekFrom::Start(offset))?;
+                    continue;
+                }
+                None => {
+                    report.truncated_bytes = data_end - pos;
+                    break;
+                }
+            }
+        };
+        if data_end - pos < (rec_size + len) as u64 {
+            report.truncated_bytes = data_end - pos;
+            break;
+        }
+        data.resize(len, 0);
+        f.read_exact(&mut data)?;
+        if report.has_checksums && record_checksum(&rec_buf, &data) != rec.checksum {
+            report.corrupt_frames.push(found.nframes);
+        }
+        found.push(&rec, pos);
+        pos += (rec_size + len) as u64;
+    }
+
+    if let Some(idx) = index
+        && (idx.nframes != found.nframes || idx.ranges != found.ranges || idx.gaps != found.gaps)
+    {
+        report.index_mismatch = true;
+    }
+    report.nframes = found.nframes;
+    report.nsynthesized = found.nsynthesized;
+    report.ranges = found.ranges;
+    report.gaps = found.gaps;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{capture_file::CaptureWriter, payload::Payload};
+
+    fn record(pkt_cnt: u64, synthesized: bool) -> FrameRecord {
+        FrameRecord {
+            pkt_cnt,
+            flags: if synthesized {
+                crate::capture_file::FRAME_SYNTHESIZED
+            } else {
+                0
+            },
+            ..Default::default()
+        }
+    }
+
+    /// `pkt_cnt`s 0..20 and 30..40, 5..8 synthesized
+    fn frames() -> Vec<Payload> {
+        (0..20)
+            .chain(30..40)
+            .map(|c| {
+                let mut p = Payload {
+                    pkt_cnt: c,
+                    ..Default::default()
+                };
+                p.data.fill(c as u8);
+                if (5..8).contains(&c) {
+                    p.mark_synthesized();
+                }
+                p
+            })
+            .collect()
+    }
+
+    const REC: usize = FrameRecord::SIZE + N_BYTE_PER_FRAME;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("syncdaq-{}-{name}.dat", std::process::id()))
+    }
+
+    /// Writes `frames` to a file, with the index if `finish`; returns its path and the
+    /// offset of the first record
+    fn capture(name: &str, finish: bool) -> (PathBuf, usize) {
+        let path = temp_path(name);
+        let mut w = CaptureWriter::create(&path, &CaptureMeta::new()).unwrap();
+        for p in &frames() {
+            w.write_frame(p).unwrap();
+        }
+        if finish {
+            w.finish().unwrap();
+        } else {
+            w.flush().unwrap();
+        }
+        let len = std::fs::metadata(&path).unwrap().len() as usize;
+        let index_len = if finish {
+            let mut f = File::open(&path).unwrap();
+            let ft = read_footer(&mut f).unwrap().unwrap();
+            ft.len as usize + IndexFooter::SIZE
+        } else {
+            0
+        };
+        (path, len - index_len - frames().len() * REC)
+    }
+
+    /// Verifies the file after `damage`, and removes it
+    fn verify_damaged(
+        name: &str,
+        finish: bool,
+        damage: impl FnOnce(&mut Vec<u8>, usize),
+    ) -> VerifyReport {
+        let (path, data_offset) = capture(name, finish);
+        let mut bytes = std::fs::read(&path).unwrap();
+        damage(&mut bytes, data_offset);
+        std::fs::write(&path, bytes).unwrap();
+        let report = verify_capture(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        report
+    }
+
+    #[test]
+    fn checksum_covers_record_and_data() {
+        let rec = [1_u8; FrameRecord::SIZE];
+        let data = [2_u8; 16];
+        let c = record_checksum(&rec, &data);
+        // the checksum field itself is left out
+        let mut rec1 = rec;
+        rec1[FrameRecord::SIZE - 1] = 0;
+        assert_eq!(record_checksum(&rec1, &data), c);
+        rec1[0] = 0;
+        assert_ne!(record_checksum(&rec1, &data), c);
+        assert_ne!(record_checksum(&rec, &data[1..]), c);
+    }
+
+    #[test]
+    fn verify_intact() {
+        for finish in [true, false] {
+            let r = verify_damaged("intact", finish, |_, _| ());
+            assert!(r.is_ok());
+            assert_eq!(r.has_index, finish);
+            assert!(r.has_checksums && !r.unverified);
+            assert_eq!((r.nframes, r.nsynthesized), (30, 3));
+            assert_eq!(
+                r.ranges,
+                [
+                    PktRange { first: 0, last: 19 },
+                    PktRange {
+                        first: 30,
+                        last: 39
+                    }
+                ]
+            );
+            assert_eq!(r.gaps, [PktRange { first: 5, last: 7 }]);
+        }
+    }
+
+    #[test]
+    fn verify_truncated() {
+        let r = verify_damaged("truncated", false, |b, _| b.truncate(b.len() - 100));
+        assert!(!r.is_ok());
+        assert_eq!(r.nframes, 29);
+        assert_eq!(r.truncated_bytes, (REC - 100) as u64);
+
+        // cut inside a record header
+        let r = verify_damaged("truncated-rec", false, |b, off| {
+            b.truncate(off + 3 * REC + 10)
+        });
+        assert_eq!((r.nframes, r.truncated_bytes), (3, 10));
+    }
+
+    #[test]
+    fn verify_corrupted() {
+        let r = verify_damaged("corrupt-data", true, |b, off| b[off + 10 * REC + 100] ^= 1);
+        assert!(!r.is_ok());
+        assert_eq!(r.corrupt_frames, [10]);
+        assert_eq!(r.nframes, 30);
+        assert!(!r.index_mismatch);
+
+        let r = verify_damaged("corrupt-rec", true, |b, off| b[off + 12 * REC] = b'X');
+        assert!(!r.is_ok());
+        assert_eq!(r.nbad_records, 1);
+        // nothing indexed after the bad record to resync at
+        assert_eq!(r.nframes, 12);
+        assert!(r.index_mismatch);
+
+        let r = verify_damaged("corrupt-index", true, |b, _| {
+            let n = b.len();
+            b[n - IndexFooter::SIZE - 1] ^= 1;
+        });
+        assert!(!r.is_ok());
+        assert!(r.has_index && r.index_mismatch);
+        assert!(r.corrupt_frames.is_empty());
+    }
+
+    #[test]
+    fn verify_not_a_capture() {
+        let path = temp_path("raw");
+        std::fs::write(&path, vec![0_u8; 3 * N_BYTE_PER_FRAME]).unwrap();
+        let r = verify_capture(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(r.unverified && !r.is_ok());
+    }
+
+    #[test]
+    fn index_runs_and_blocks() {
+        let mut idx = FrameIndex::default();
+        let cnts = (0..1500).chain(2000..2700);
+        for (n, c) in cnts.enumerate() {
+            idx.push(
+                &record(c, (100..110).contains(&c) || c == 2000),
+                10 * n as u64,
+            );
+        }
+        assert_eq!((idx.nframes, idx.nsynthesized), (2200, 11));
+        assert_eq!(
+            idx.ranges,
+            [
+                PktRange {
+                    first: 0,
+                    last: 1499
+                },
+                PktRange {
+                    first: 2000,
+                    last: 2699
+                }
+            ]
+        );
+        assert_eq!(idx.ranges[0].count(), 1500);
+        assert_eq!(
+            idx.gaps,
+            [
+                PktRange {
+                    first: 100,
+                    last: 109
+                },
+                PktRange {
+                    first: 2000,
+                    last: 2000
+                }
+            ]
+        );
+        assert_eq!(idx.blocks.len(), 3);
+        for (n, block) in [(0, 0), (1023, 0), (1024, 1024), (2199, 2048)] {
+            let b = idx.block_of(n).unwrap();
+            assert_eq!((b.frame_no, b.offset), (block, 10 * block));
+        }
+        assert!(FrameIndex::default().block_of(0).is_none());
+    }
+}
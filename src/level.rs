@@ -0,0 +1,266 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crossbeam::channel::Receiver;
+use lockfree_object_pool::LinearOwnedReusable;
+
+use crate::{payload::Payload, sdr::SdrCtrl, utils::as_complex_t};
+
+const NVALUES: usize = 1 << 16;
+
+/// Full resolution histogram of the I and Q components of one port
+#[derive(Clone, Debug)]
+pub struct LevelStats {
+    pub hist_re: Vec<u64>,
+    pub hist_im: Vec<u64>,
+    pub nsamples: u64,
+    pub nclipped: u64,
+    sum_sq: f64,
+}
+
+impl Default for LevelStats {
+    fn default() -> Self {
+        Self {
+            hist_re: vec![0; NVALUES],
+            hist_im: vec![0; NVALUES],
+            nsamples: 0,
+            nclipped: 0,
+            sum_sq: 0.0,
+        }
+    }
+}
+
+fn bin_of(x: i16) -> usize {
+    (x as i32 - i16::MIN as i32) as usize
+}
+
+fn value_of(bin: usize) -> i16 {
+    (bin as i32 + i16::MIN as i32) as i16
+}
+
+fn is_clipped(x: i16) -> bool {
+    x == i16::MAX || x <= -i16::MAX
+}
+
+impl LevelStats {
+    pub fn reset(&mut self) {
+        self.hist_re.fill(0);
+        self.hist_im.fill(0);
+        self.nsamples = 0;
+        self.nclipped = 0;
+        self.sum_sq = 0.0;
+    }
+
+    pub fn accumulate(&mut self, payload: &Payload) {
+        for x in as_complex_t::<i16>(&payload.data) {
+            self.hist_re[bin_of(x.re)] += 1;
+            self.hist_im[bin_of(x.im)] += 1;
+            self.sum_sq += (x.re as f64).powi(2) + (x.im as f64).powi(2);
+            if is_clipped(x.re) || is_clipped(x.im) {
+                self.nclipped += 1;
+            }
+        }
+        self.nsamples += (payload.data.len() / 4) as u64;
+    }
+
+    /// RMS of a single component, I and Q pooled
+    pub fn rms(&self) -> f64 {
+        if self.nsamples == 0 {
+            0.0
+        } else {
+            (self.sum_sq / (2 * self.nsamples) as f64).sqrt()
+        }
+    }
+
+    /// fraction of complex samples with I or Q at full scale
+    pub fn clip_fraction(&self) -> f64 {
+        if self.nsamples == 0 {
+            0.0
+        } else {
+            self.nclipped as f64 / self.nsamples as f64
+        }
+    }
+
+    pub fn peak(&self) -> i16 {
+        let peak_of = |h: &[u64]| {
+            let lo = h.iter().position(|&c| c > 0).map(value_of).unwrap_or(0);
+            let hi = h.iter().rposition(|&c| c > 0).map(value_of).unwrap_or(0);
+            lo.saturating_abs().max(hi)
+        };
+        peak_of(&self.hist_re).max(peak_of(&self.hist_im))
+    }
+
+    /// number of bits, sign included, needed to hold the largest sample
+    pub fn peak_bits(&self) -> u32 {
+        16 - self.peak().leading_zeros() + 1
+    }
+
+    /// Shannon entropy of the sample distribution, averaged over I and Q, in bits
+    pub fn effective_bits(&self) -> f64 {
+        if self.nsamples == 0 {
+            return 0.0;
+        }
+        let n = self.nsamples as f64;
+        let entropy = |h: &[u64]| {
+            h.iter()
+                .filter(|&&c| c > 0)
+                .map(|&c| {
+                    let p = c as f64 / n;
+                    -p * p.log2()
+                })
+                .sum::<f64>()
+        };
+        (entropy(&self.hist_re) + entropy(&self.hist_im)) / 2.0
+    }
+
+    /// histogram of I and Q merged into `nbins` equal bins over the full i16 range
+    pub fn rebinned(&self, nbins: usize) -> Vec<u64> {
+        let mut result = vec![0; nbins];
+        for (i, (a, b)) in self.hist_re.iter().zip(&self.hist_im).enumerate() {
+            result[i * nbins / NVALUES] += a + b;
+        }
+        result
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct PortLevels {
+    pub ports: BTreeMap<u32, LevelStats>,
+}
+
+impl PortLevels {
+    pub fn accumulate(&mut self, payload: &Payload) {
+        self.ports
+            .entry(payload.port_id)
+            .or_default()
+            .accumulate(payload);
+    }
+
+    pub fn reset(&mut self) {
+        self.ports.clear();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AutoLevelCfg {
+    pub target_rms_lo: f64,
+    pub target_rms_hi: f64,
+    pub max_clip_fraction: f64,
+    pub min_shift: u32,
+    pub max_shift: u32,
+    pub init_shift: u32,
+    pub nframes: usize,
+    /// frames discarded after each BitShift so that stale data is not measured
+    pub nsettle: usize,
+    pub max_iter: usize,
+}
+
+impl Default for AutoLevelCfg {
+    fn default() -> Self {
+        Self {
+            target_rms_lo: 256.0,
+            target_rms_hi: 1024.0,
+            max_clip_fraction: 1e-6,
+            min_shift: 0,
+            max_shift: 16,
+            init_shift: 8,
+            nframes: 1024,
+            nsettle: 4096,
+            max_iter: 16,
+        }
+    }
+}
+
+/// What one step of `auto_level` measured
+#[derive(Clone, Copy, Debug)]
+pub struct LevelStep {
+    pub shift_bits: u32,
+    pub rms: f64,
+    pub clip_fraction: f64,
+    pub effective_bits: f64,
+}
+
+#[derive(Debug)]
+pub struct AutoLevelResult {
+    pub shift_bits: u32,
+    pub stats: LevelStats,
+    pub converged: bool,
+    /// every shift tried, in order, the last one is `shift_bits`
+    pub steps: Vec<LevelStep>,
+}
+
+pub fn measure(rx: &Receiver<LinearOwnedReusable<Payload>>, nframes: usize) -> LevelStats {
+    let mut stats = LevelStats::default();
+    for _ in 0..nframes {
+        let payload = rx.recv().expect("failed to recv payload");
+        stats.accumulate(&payload);
+    }
+    stats
+}
+
+/// Steps `BitShift` until the RMS is inside the target range without clipping.
+///
+/// A larger `shift_bits` is assumed to scale the samples down by 2 per bit.
+pub fn auto_level(
+    ctrl: &SdrCtrl,
+    rx: &Receiver<LinearOwnedReusable<Payload>>,
+    cfg: &AutoLevelCfg,
+) -> std::io::Result<AutoLevelResult> {
+    let target = (cfg.target_rms_lo * cfg.target_rms_hi).sqrt();
+    let mut shift = cfg.init_shift.clamp(cfg.min_shift, cfg.max_shift);
+    let mut stats = LevelStats::default();
+    let mut steps = Vec::new();
+
+    for _ in 0..cfg.max_iter {
+        ctrl.set_bit_shift(shift)?;
+        // drop what was queued before the new shift took effect
+        while rx.try_recv().is_ok() {}
+        for _ in 0..cfg.nsettle {
+            if rx.recv_timeout(Duration::from_secs(1)).is_err() {
+                break;
+            }
+        }
+        stats = measure(rx, cfg.nframes);
+        let rms = stats.rms();
+        let clip = stats.clip_fraction();
+        steps.push(LevelStep {
+            shift_bits: shift,
+            rms,
+            clip_fraction: clip,
+            effective_bits: stats.effective_bits(),
+        });
+
+        let in_range = rms >= cfg.target_rms_lo && rms <= cfg.target_rms_hi;
+        if in_range && clip <= cfg.max_clip_fraction {
+            return Ok(AutoLevelResult {
+                shift_bits: shift,
+                stats,
+                converged: true,
+                steps,
+            });
+        }
+
+        let mut delta = if rms > 0.0 {
+            (rms / target).log2().round() as i64
+        } else {
+            -1
+        };
+        if clip > cfg.max_clip_fraction {
+            delta = delta.max(1);
+        }
+        if delta == 0 {
+            delta = if rms > cfg.target_rms_hi { 1 } else { -1 };
+        }
+        let next = (shift as i64 + delta).clamp(cfg.min_shift as i64, cfg.max_shift as i64) as u32;
+        if next == shift || steps.iter().any(|s| s.shift_bits == next) {
+            break;
+        }
+        shift = next;
+    }
+
+    Ok(AutoLevelResult {
+        shift_bits: shift,
+        stats,
+        converged: false,
+        steps,
+    })
+}
@@ -7,3 +7,19 @@ pub mod ctrl_msg;
 pub mod c_interface;
 
 pub mod sdr;
+pub mod level;
+pub mod spectrometer;
+pub mod beamformer;
+pub mod capture_file;
+pub mod compress;
+pub mod sigmf;
+pub mod vdif;
+pub mod rotate;
+pub mod trigger;
+pub mod disk_writer;
+pub mod integrity;
+pub mod replay;
+pub mod requant;
+#[cfg(feature = "hdf5")]
+pub mod h5;
+pub mod xcorr;
@@ -2,6 +2,9 @@
 
 pub const N_BYTE_PER_FRAME: usize = 8192;
 
+/// tail_magic written into frames synthesized by `recv_pkt` to fill dropped packets
+pub const GAP_TAIL_MAGIC: u64 = 0xdead_beef_dead_beef;
+
 pub const fn n_pt_per_frame<T: Sized>()->usize{
     N_BYTE_PER_FRAME/std::mem::size_of::<T>()/2
 }
@@ -32,4 +35,12 @@ impl Payload {
         self.pkt_cnt = rhs.pkt_cnt;
         self.tail_magic = rhs.tail_magic;
     }
+
+    pub fn mark_synthesized(&mut self) {
+        self.tail_magic = GAP_TAIL_MAGIC;
+    }
+
+    pub fn is_synthesized(&self) -> bool {
+        self.tail_magic == GAP_TAIL_MAGIC
+    }
 }
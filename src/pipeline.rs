@@ -3,7 +3,10 @@ use std::time::{Duration, Instant};
 use std::{
     net::{Ipv4Addr, UdpSocket},
     ops::Deref,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use chrono::Local;
@@ -12,7 +15,7 @@ use lockfree_object_pool::{LinearObjectPool, LinearOwnedReusable};
 
 
 use crate::{
-    payload::Payload,
+    payload::{Payload, n_pt_per_frame},
     utils::as_mut_u8_slice,
 };
 
@@ -68,6 +71,108 @@ pub enum RecvCmd {
     Destroy,
 }
 
+/// Window of the sample rate estimate of `RecvCounters`
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Frame counts of a stream, updated by the thread passing the frames on and read from any
+#[derive(Debug)]
+pub struct RecvCounters {
+    t0: Instant,
+    nreceived: AtomicU64,
+    nsynthesized: AtomicU64,
+    nrejected: AtomicU64,
+    ndropped: AtomicU64,
+    /// ns after `t0` of the last frame, 0 before the first
+    last_frame_ns: AtomicU64,
+    window_start_ns: AtomicU64,
+    window_nframes: AtomicU64,
+    /// `f64` bits
+    smp_rate: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecvStats {
+    /// frames received from the board, or read from files
+    pub nreceived: u64,
+    /// zero-filled frames standing in for packets lost on the way
+    pub nsynthesized: u64,
+    /// datagrams ignored for not being a frame
+    pub nrejected: u64,
+    /// frames discarded after being passed on, e.g. to align ports
+    pub ndropped: u64,
+    /// samples per second over the last second the stream ran, 0 before
+    pub smp_rate: f64,
+    pub since_last_frame: Option<Duration>,
+}
+
+impl Default for RecvCounters {
+    fn default() -> Self {
+        Self {
+            t0: Instant::now(),
+            nreceived: AtomicU64::new(0),
+            nsynthesized: AtomicU64::new(0),
+            nrejected: AtomicU64::new(0),
+            ndropped: AtomicU64::new(0),
+            last_frame_ns: AtomicU64::new(0),
+            window_start_ns: AtomicU64::new(0),
+            window_nframes: AtomicU64::new(0),
+            smp_rate: AtomicU64::new(0),
+        }
+    }
+}
+
+impl RecvCounters {
+    fn now_ns(&self) -> u64 {
+        self.t0.elapsed().as_nanos() as u64 + 1
+    }
+
+    /// Counts a frame passed on, only to be called by one thread
+    pub fn add_frame(&self, synthesized: bool) {
+        if synthesized {
+            self.nsynthesized.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.nreceived.fetch_add(1, Ordering::Relaxed);
+        }
+        let now = self.now_ns();
+        let last = self.last_frame_ns.swap(now, Ordering::Relaxed);
+        let start = self.window_start_ns.load(Ordering::Relaxed);
+        let n = self.window_nframes.fetch_add(1, Ordering::Relaxed);
+        let window = RATE_WINDOW.as_nanos() as u64;
+        if start == 0 || now - last > window {
+            // the first frame, or the stream paused, which is not part of the rate
+            self.window_start_ns.store(now, Ordering::Relaxed);
+            self.window_nframes.store(0, Ordering::Relaxed);
+        } else if now - start >= window {
+            let rate =
+                ((n + 1) * n_pt_per_frame::<i16>() as u64) as f64 * 1e9 / (now - start) as f64;
+            self.smp_rate.store(rate.to_bits(), Ordering::Relaxed);
+            self.window_start_ns.store(now, Ordering::Relaxed);
+            self.window_nframes.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_rejected(&self) {
+        self.nrejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_dropped(&self) {
+        self.ndropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RecvStats {
+        let last = self.last_frame_ns.load(Ordering::Relaxed);
+        RecvStats {
+            nreceived: self.nreceived.load(Ordering::Relaxed),
+            nsynthesized: self.nsynthesized.load(Ordering::Relaxed),
+            nrejected: self.nrejected.load(Ordering::Relaxed),
+            ndropped: self.ndropped.load(Ordering::Relaxed),
+            smp_rate: f64::from_bits(self.smp_rate.load(Ordering::Relaxed)),
+            since_last_frame: (last != 0)
+                .then(|| Duration::from_nanos(self.now_ns().saturating_sub(last))),
+        }
+    }
+}
+
 // pub fn fake_dev(tx_payload: Sender<LinearOwnedReusable<Payload>>, rx_cmd: Receiver<RecvCmd>) {
 //     let mut last_print_time = Instant::now();
 //     let t0 = Instant::now();
@@ -120,6 +225,16 @@ pub fn recv_pkt(
     socket: MaybeMulticastReceiver,
     tx_payload: Sender<LinearOwnedReusable<Payload>>,
     rx_cmd: Receiver<RecvCmd>,
+) {
+    recv_pkt_counted(socket, tx_payload, rx_cmd, &RecvCounters::default())
+}
+
+/// Like `recv_pkt`, counting the frames into `counters`
+pub fn recv_pkt_counted(
+    socket: MaybeMulticastReceiver,
+    tx_payload: Sender<LinearOwnedReusable<Payload>>,
+    rx_cmd: Receiver<RecvCmd>,
+    counters: &RecvCounters,
 ) {
     let mut last_print_time = Instant::now();
     let print_interval = Duration::from_secs(2);
@@ -152,6 +267,7 @@ pub fn recv_pkt(
         match socket.recv_from(buf) {
             Ok((s, _a)) => {
                 if s != std::mem::size_of::<Payload>() {
+                    counters.add_rejected();
                     continue;
                 }
             }
@@ -201,6 +317,7 @@ pub fn recv_pkt(
                 }
                 nreceived += 1;
                 if let Ok(()) = tx_payload.send(payload) {
+                    counters.add_frame(false);
                     break;
                 } else {
                     return;
@@ -212,6 +329,7 @@ pub fn recv_pkt(
             let mut payload1 = pool.pull_owned();
             payload1.copy_header(&payload);
             payload1.pkt_cnt = *c;
+            payload1.mark_synthesized();
             if tx_payload.is_full() {
                 //eprint!("O");
                 if !rx_cmd.is_empty() {
@@ -225,6 +343,7 @@ pub fn recv_pkt(
             if tx_payload.send(payload1).is_err() {
                 return;
             }
+            counters.add_frame(true);
 
             *c += 1;
         }
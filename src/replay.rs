@@ -0,0 +1,211 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{Receiver, Sender};
+use lockfree_object_pool::{LinearObjectPool, LinearOwnedReusable};
+
+use crate::{
+    capture_file::{CaptureMeta, CaptureReader},
+    payload::{Payload, n_pt_per_frame},
+    pipeline::{RecvCmd, RecvCounters},
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct FileSourceCfg {
+    /// played one after the other, e.g. the segments of a rotated capture
+    pub paths: Vec<PathBuf>,
+    /// pace frames at the sample rate instead of as fast as they are consumed
+    pub realtime: bool,
+    /// in Hz, overrides the one in the file meta for pacing
+    pub smp_rate: Option<f64>,
+    /// start over after the last file, with `pkt_cnt` continuing to count up
+    pub looping: bool,
+    /// skip frames before this `pkt_cnt`
+    pub start_pkt_cnt: Option<u64>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FileSourceArgs {
+    #[clap(short = 'i', long = "in", num_args(1..), value_name = "<capture file> ...")]
+    pub input: Vec<String>,
+
+    #[clap(long = "realtime", help = "replay at the recorded sample rate")]
+    pub realtime: bool,
+
+    #[clap(long = "loop", help = "replay the files forever")]
+    pub looping: bool,
+
+    #[clap(long = "start", value_name = "pkt_cnt to start the replay at")]
+    pub start_pkt_cnt: Option<u64>,
+
+    #[clap(long = "replay-rate", value_name = "sample rate in Hz for --realtime")]
+    pub smp_rate: Option<f64>,
+}
+
+impl FileSourceArgs {
+    pub fn to_cfg(&self) -> FileSourceCfg {
+        FileSourceCfg {
+            paths: self.input.iter().map(PathBuf::from).collect(),
+            realtime: self.realtime,
+            smp_rate: self.smp_rate,
+            looping: self.looping,
+            start_pkt_cnt: self.start_pkt_cnt,
+        }
+    }
+}
+
+/// Positions `r` at the first frame with `pkt_cnt >= target`, returns false if there is none
+fn seek_pkt_cnt(
+    r: &mut CaptureReader<BufReader<File>>,
+    target: u64,
+    buf: &mut Payload,
+) -> std::io::Result<bool> {
+    let n = r.nframes()?;
+    if n == 0 {
+        return Ok(false);
+    }
+    r.seek_frame(n - 1)?;
+    r.read_frame(buf)?;
+    if buf.pkt_cnt < target {
+        return Ok(false);
+    }
+    r.seek_frame(0)?;
+    r.read_frame(buf)?;
+    let first = buf.pkt_cnt;
+
+    // frames are usually contiguous, so try the direct guess first
+    let guess = target.saturating_sub(first);
+    if guess < n {
+        r.seek_frame(guess)?;
+        r.read_frame(buf)?;
+        if buf.pkt_cnt == target {
+            r.seek_frame(guess)?;
+            return Ok(true);
+        }
+    }
+    r.seek_frame(0)?;
+    for i in 0..n {
+        r.read_frame(buf)?;
+        if buf.pkt_cnt >= target {
+            r.seek_frame(i)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Replays capture files as a stream of frames, on the same channel type as `recv_pkt`
+pub struct FileSource {
+    cfg: FileSourceCfg,
+    /// meta of the first file
+    pub meta: CaptureMeta,
+    frame_interval: Option<Duration>,
+    /// frames sent by `run`
+    pub counters: Arc<RecvCounters>,
+}
+
+impl FileSource {
+    /// Checks that all files can be read and that pacing is possible
+    pub fn open(cfg: FileSourceCfg) -> std::io::Result<Self> {
+        let mut meta = None;
+        for p in &cfg.paths {
+            let r = CaptureReader::open(p)?;
+            meta.get_or_insert(r.meta);
+        }
+        let meta = meta.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file to replay")
+        })?;
+
+        let frame_interval = if cfg.realtime {
+            let smp_rate = cfg.smp_rate.or(meta.smp_rate).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "realtime replay needs the sample rate",
+                )
+            })?;
+            Some(Duration::from_secs_f64(
+                n_pt_per_frame::<i16>() as f64 / smp_rate,
+            ))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            cfg,
+            meta,
+            frame_interval,
+            counters: Arc::new(RecvCounters::default()),
+        })
+    }
+
+    /// Sends frames until the files end, the receiver is dropped or `RecvCmd::Destroy` arrives
+    pub fn run(
+        self,
+        tx_payload: Sender<LinearOwnedReusable<Payload>>,
+        rx_cmd: Receiver<RecvCmd>,
+    ) -> std::io::Result<()> {
+        let pool: Arc<LinearObjectPool<Payload>> =
+            Arc::new(LinearObjectPool::new(Payload::default, |v| {
+                v.pkt_cnt = 0;
+                v.tail_magic = 0;
+            }));
+        let mut scratch = Payload::default();
+        let t0 = Instant::now();
+        let mut nsent = 0_u64;
+        let mut cnt_offset = 0_u64;
+        let mut next_cnt = None;
+
+        loop {
+            let mut first_cnt = None;
+            for p in &self.cfg.paths {
+                let mut r = CaptureReader::open(p)?;
+                if let Some(start) = self.cfg.start_pkt_cnt
+                    && !seek_pkt_cnt(&mut r, start, &mut scratch)?
+                {
+                    continue;
+                }
+                loop {
+                    if let Ok(RecvCmd::Destroy) = rx_cmd.try_recv() {
+                        return Ok(());
+                    }
+                    let mut payload = pool.pull_owned();
+                    if r.read_frame(&mut payload)?.is_none() {
+                        break;
+                    }
+                    first_cnt.get_or_insert(payload.pkt_cnt);
+                    payload.pkt_cnt += cnt_offset;
+
+                    if let Some(dt) = self.frame_interval {
+                        let due = t0 + dt.mul_f64(nsent as f64);
+                        let now = Instant::now();
+                        // sleeping per frame is too coarse at typical frame rates
+                        if due > now + Duration::from_millis(1) {
+                            std::thread::sleep(due - now);
+                        }
+                        nsent += 1;
+                    }
+                    next_cnt = Some(payload.pkt_cnt + 1);
+                    let synthesized = payload.is_synthesized();
+                    if tx_payload.send(payload).is_err() {
+                        return Ok(());
+                    }
+                    self.counters.add_frame(synthesized);
+                }
+            }
+
+            let Some(first_cnt) = first_cnt else {
+                return Ok(());
+            };
+            if !self.cfg.looping {
+                return Ok(());
+            }
+            // the next pass continues right after the last frame sent
+            cnt_offset = next_cnt.unwrap_or(0) - first_cnt;
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use num::Complex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    payload::{N_BYTE_PER_FRAME, Payload},
+    utils::as_complex_t,
+    vdif::{VDIF_2BIT_THRESHOLD, VDIF_8BIT_SIGMA},
+};
+
+/// optimal step of a uniform 16 level quantizer for gaussian noise, in units of sigma
+pub const REQUANT_4BIT_STEP: f64 = 0.3352;
+
+/// Step between quantization levels, in units of the input sigma, used by default
+pub fn default_step(bits: u32) -> f64 {
+    match bits {
+        2 => VDIF_2BIT_THRESHOLD,
+        4 => REQUANT_4BIT_STEP,
+        _ => 1.0 / VDIF_8BIT_SIGMA,
+    }
+}
+
+/// Requantization of complex i16 frames to fewer bits per component.
+///
+/// The quantizer is uniform and midrise: code `k` of `bits` stands for
+/// `(k - 2^(bits-1) + 0.5) * step * sigma`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RequantCfg {
+    /// bits per component: 2, 4 or 8
+    pub bits: u32,
+    /// in units of sigma
+    pub step: f64,
+    /// input sigma in ADC counts; a running RMS per port if `None`
+    pub fixed_sigma: Option<f64>,
+}
+
+impl RequantCfg {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            step: default_step(bits),
+            fixed_sigma: None,
+        }
+    }
+
+    pub fn check(&self) -> std::io::Result<()> {
+        if ![2, 4, 8].contains(&self.bits) || self.step <= 0.0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid requantization {self:?}"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Bytes a requantized frame takes: the f32 step in ADC counts, then the codes
+pub fn requant_frame_len(bits: u32) -> usize {
+    4 + N_BYTE_PER_FRAME * bits as usize / 16
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RequantArgs {
+    #[clap(long = "requant", value_name = "bits per component: 2, 4 or 8")]
+    pub bits: Option<u32>,
+
+    #[clap(
+        long = "requant-step",
+        value_name = "level step in units of sigma, default optimal for the bits"
+    )]
+    pub step: Option<f64>,
+
+    #[clap(
+        long = "requant-sigma",
+        value_name = "fixed input sigma in ADC counts, default running RMS"
+    )]
+    pub fixed_sigma: Option<f64>,
+}
+
+impl RequantArgs {
+    pub fn to_cfg(&self) -> Option<RequantCfg> {
+        let bits = self.bits?;
+        Some(RequantCfg {
+            bits,
+            step: self.step.unwrap_or(default_step(bits)),
+            fixed_sigma: self.fixed_sigma,
+        })
+    }
+}
+
+/// Running RMS of the frame data per port, the input sigma of requantizers
+#[derive(Default)]
+pub struct RunningRms {
+    rms: BTreeMap<u32, f64>,
+}
+
+impl RunningRms {
+    /// Updates the RMS of the port with the frame and returns it, at least 1 ADC count
+    pub fn sigma(&mut self, payload: &Payload) -> f64 {
+        // synthesized frames are zeros and would drag the RMS down
+        if payload.is_synthesized() {
+            return self.rms.get(&payload.port_id).map_or(1.0, |r| r.max(1.0));
+        }
+        let data = as_complex_t::<i16>(&payload.data);
+        let ms = data
+            .iter()
+            .map(|x| (x.re as f64).powi(2) + (x.im as f64).powi(2))
+            .sum::<f64>()
+            / (2 * data.len()) as f64;
+        let r = self
+            .rms
+            .entry(payload.port_id)
+            .and_modify(|r| *r = (0.99 * *r * *r + 0.01 * ms).sqrt())
+            .or_insert(ms.sqrt());
+        r.max(1.0)
+    }
+}
+
+/// Converts frames as given by a `RequantCfg`, keeping a running RMS per port
+pub struct Requantizer {
+    cfg: RequantCfg,
+    rms: RunningRms,
+}
+
+impl Requantizer {
+    pub fn new(cfg: RequantCfg) -> std::io::Result<Self> {
+        cfg.check()?;
+        Ok(Self {
+            cfg,
+            rms: RunningRms::default(),
+        })
+    }
+
+    pub fn cfg(&self) -> &RequantCfg {
+        &self.cfg
+    }
+
+    fn sigma(&mut self, payload: &Payload) -> f64 {
+        self.cfg
+            .fixed_sigma
+            .unwrap_or_else(|| self.rms.sigma(payload))
+    }
+
+    /// Appends the requantized frame to `out`, returns the number of bytes appended.
+    ///
+    /// Codes are packed from the lowest bits up, I before Q, as in VDIF.
+    pub fn requantize(&mut self, payload: &Payload, out: &mut Vec<u8>) -> usize {
+        let bits = self.cfg.bits;
+        let step = (self.cfg.step * self.sigma(payload)) as f32;
+        let n0 = out.len();
+        out.extend_from_slice(&step.to_le_bytes());
+
+        let offset = (1_i32 << (bits - 1)) as f32;
+        let max = (1_i32 << bits) - 1;
+        let per_byte = 8 / bits as usize;
+        let samples = as_complex_t::<i16>(&payload.data);
+        let mut byte = 0_u8;
+        for (i, x) in samples.iter().flat_map(|x| [x.re, x.im]).enumerate() {
+            let k = ((x as f32 / step + offset).floor() as i32).clamp(0, max) as u8;
+            byte |= k << (bits as usize * (i % per_byte));
+            if i % per_byte == per_byte - 1 {
+                out.push(byte);
+                byte = 0;
+            }
+        }
+        out.len() - n0
+    }
+}
+
+/// Expands a frame written by `Requantizer::requantize` into `out`, in ADC counts
+pub fn expand_frame(src: &[u8], bits: u32, out: &mut [Complex<f32>]) -> std::io::Result<()> {
+    if src.len() != requant_frame_len(bits) || out.len() * 2 * bits as usize != (src.len() - 4) * 8
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "invalid requantized frame length",
+        ));
+    }
+    let step = f32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+    let offset = (1_i32 << (bits - 1)) as f32 - 0.5;
+    let mask = ((1_u32 << bits) - 1) as u8;
+    let per_byte = 8 / bits as usize;
+    let value = |i: usize| {
+        let k = (src[4 + i / per_byte] >> (bits as usize * (i % per_byte))) & mask;
+        (k as f32 - offset) * step
+    };
+    for (i, y) in out.iter_mut().enumerate() {
+        *y = Complex::new(value(2 * i), value(2 * i + 1));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::n_pt_per_frame;
+
+    const SIGMA: f64 = 100.0;
+
+    /// frame whose components, I then Q of each sample, are `f(j)`
+    fn payload(f: impl Fn(usize) -> i16) -> Payload {
+        let mut p = Payload::default();
+        for (j, x) in p.data.chunks_exact_mut(2).enumerate() {
+            x.copy_from_slice(&f(j).to_le_bytes());
+        }
+        p
+    }
+
+    fn requantize(bits: u32, p: &Payload) -> (Vec<u8>, Vec<Complex<f32>>) {
+        let mut cfg = RequantCfg::new(bits);
+        cfg.fixed_sigma = Some(SIGMA);
+        let mut out = Vec::new();
+        let n = Requantizer::new(cfg).unwrap().requantize(p, &mut out);
+        assert_eq!(
+            (n, out.len()),
+            (requant_frame_len(bits), requant_frame_len(bits))
+        );
+        let mut y = vec![Complex::default(); n_pt_per_frame::<i16>()];
+        expand_frame(&out, bits, &mut y).unwrap();
+        (out, y)
+    }
+
+    fn components(y: &[Complex<f32>]) -> Vec<f32> {
+        y.iter().flat_map(|y| [y.re, y.im]).collect()
+    }
+
+    #[test]
+    fn ramp_within_half_step() {
+        let n = 2 * n_pt_per_frame::<i16>();
+        for bits in [2, 4, 8] {
+            let step = (default_step(bits) * SIGMA) as f32;
+            let full_scale = (1 << (bits - 1)) as f32 * step;
+            let x = |j: usize| (full_scale * (2.0 * j as f32 / n as f32 - 1.0)).round() as i16;
+            let (out, y) = requantize(bits, &payload(x));
+            assert_eq!(f32::from_le_bytes(out[..4].try_into().unwrap()), step);
+            for (j, y) in components(&y).into_iter().enumerate() {
+                assert!(
+                    (y - x(j) as f32).abs() <= step / 2.0 + 1e-3,
+                    "{bits} bits: {} expanded to {y}",
+                    x(j)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn midrise_and_clamp() {
+        for bits in [2, 4, 8] {
+            let step = (default_step(bits) * SIGMA) as f32;
+            let top = ((1 << (bits - 1)) as f32 - 0.5) * step;
+            let x = [0, -1, i16::MAX, i16::MIN];
+            let (_, y) = requantize(bits, &payload(|j| x[j % 4]));
+            for (j, y) in components(&y).into_iter().enumerate() {
+                assert_eq!(
+                    y,
+                    [step / 2.0, -step / 2.0, top, -top][j % 4],
+                    "{bits} bits"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn code_packing() {
+        // component j at the middle of level j, codes packed from the lowest bits up
+        let expected: [(u32, &[u8]); 3] = [
+            (2, &[0b11_10_01_00]),
+            (4, &[0x10, 0x32]),
+            (8, &[0, 1, 2, 3]),
+        ];
+        for (bits, bytes) in expected {
+            let step = default_step(bits) * SIGMA;
+            let level = |k: usize| ((k as f64 - (1 << (bits - 1)) as f64 + 0.5) * step) as i16;
+            let (out, _) = requantize(bits, &payload(|j| level(j % 4)));
+            assert_eq!(&out[4..4 + bytes.len()], bytes, "{bits} bits");
+        }
+    }
+
+    #[test]
+    fn expand_rejects_wrong_lengths() {
+        let (out, mut y) = requantize(4, &payload(|j| j as i16));
+        assert!(expand_frame(&out[..out.len() - 1], 4, &mut y).is_err());
+        assert!(expand_frame(&out, 2, &mut y).is_err());
+        assert!(expand_frame(&out, 4, &mut y[1..]).is_err());
+        assert!(expand_frame(&out, 4, &mut y).is_ok());
+    }
+}
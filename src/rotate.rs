@@ -0,0 +1,280 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    capture_file::{CaptureMeta, FrameSink, OutputFormat},
+    compress::Compression,
+    payload::{N_BYTE_PER_FRAME, Payload},
+};
+
+/// When to start a new file; a limit of `None` is never reached
+#[derive(Clone, Debug, Default)]
+pub struct RotateCfg {
+    /// file name template, see `expand_template`
+    pub template: String,
+    pub max_frames: Option<u64>,
+    /// in bytes of frame data
+    pub max_bytes: Option<u64>,
+    /// wall-clock length of a file, boundaries are aligned to multiples of it since the unix epoch
+    pub max_duration: Option<Duration>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RotateArgs {
+    #[clap(
+        long = "rotate-secs",
+        value_name = "wall-clock seconds per file, aligned, e.g. 3600 for hourly files"
+    )]
+    pub rotate_secs: Option<u64>,
+
+    #[clap(long = "rotate-mb", value_name = "MB of frame data per file")]
+    pub rotate_mega_byte: Option<u64>,
+}
+
+impl RotateArgs {
+    pub fn to_cfg(&self, template: &str, max_frames: Option<u64>) -> RotateCfg {
+        RotateCfg {
+            template: template.to_string(),
+            max_frames,
+            max_bytes: self.rotate_mega_byte.map(|m| m * 1024 * 1024),
+            max_duration: self.rotate_secs.map(Duration::from_secs),
+        }
+    }
+}
+
+/// Sidecar `<file>.yaml` written next to every completed file
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SegmentInfo {
+    pub seq: u64,
+    pub port_id: u32,
+    pub first_pkt_cnt: u64,
+    pub last_pkt_cnt: u64,
+    pub nframes: u64,
+    pub nsynthesized: u64,
+    /// RFC 3339
+    pub start_utc: String,
+    /// RFC 3339
+    pub end_utc: String,
+    /// frame data bytes before / after compression, for compressed or requantized captures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_ratio: Option<f64>,
+}
+
+/// Expands `{utc}` (start time as `%Y%m%dT%H%M%SZ`), `{port}`, `{device}` and `{seq}`.
+///
+/// Characters of the device name other than letters, digits and `-` are replaced by `_`,
+/// so that e.g. the dots of an ip address are not taken for an extension later.
+pub fn expand_template(
+    template: &str,
+    utc: &DateTime<Utc>,
+    port_id: u32,
+    device: Option<&str>,
+    seq: u64,
+) -> String {
+    let device: String = device
+        .unwrap_or("unknown")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    template
+        .replace("{utc}", &utc.format("%Y%m%dT%H%M%SZ").to_string())
+        .replace("{port}", &port_id.to_string())
+        .replace("{device}", &device)
+        .replace("{seq}", &seq.to_string())
+}
+
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut s = path.as_ref().as_os_str().to_os_string();
+    s.push(".yaml");
+    PathBuf::from(s)
+}
+
+/// `dir/.name.part`, the name a file is written under until it is complete
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.part"))
+}
+
+fn slot_of(t: &DateTime<Utc>, d: Duration) -> u64 {
+    t.timestamp_millis() as u64 / d.as_millis().max(1) as u64
+}
+
+struct Segment {
+    sink: FrameSink,
+    path: PathBuf,
+    temp: PathBuf,
+    start: DateTime<Utc>,
+    slot: Option<u64>,
+    info: SegmentInfo,
+}
+
+/// A `FrameSink` that starts a new file whenever a `RotateCfg` limit is reached.
+///
+/// Files are written under a hidden temporary name and renamed to the expanded
+/// template once complete, together with a `SegmentInfo` sidecar, so anything that
+/// shows up under the final name is complete.
+pub struct RotatingSink {
+    cfg: RotateCfg,
+    meta: CaptureMeta,
+    format: OutputFormat,
+    seq: u64,
+    current: Option<Segment>,
+}
+
+impl RotatingSink {
+    pub fn new(cfg: RotateCfg, meta: &CaptureMeta, format: OutputFormat) -> Self {
+        Self {
+            cfg,
+            meta: meta.clone(),
+            format,
+            seq: 0,
+            current: None,
+        }
+    }
+
+    /// sequence number of the next file to be opened
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Restarts numbering at `seq`, applies from the next file on
+    pub fn set_seq(&mut self, seq: u64) {
+        self.seq = seq;
+    }
+
+    fn open(&mut self, payload: &Payload) -> std::io::Result<()> {
+        let now = Utc::now();
+        let path = PathBuf::from(expand_template(
+            &self.cfg.template,
+            &now,
+            payload.port_id,
+            self.meta.device.as_deref(),
+            self.seq,
+        ));
+        let temp = temp_path(&path);
+        let mut meta = self.meta.clone();
+        let start_utc = now.to_rfc3339_opts(SecondsFormat::Micros, true);
+        meta.start_utc = Some(start_utc.clone());
+        let sink = FrameSink::create(&temp, &meta, self.format)?;
+        self.current = Some(Segment {
+            sink,
+            path,
+            temp,
+            start: now,
+            slot: self.cfg.max_duration.map(|d| slot_of(&now, d)),
+            info: SegmentInfo {
+                seq: self.seq,
+                port_id: payload.port_id,
+                first_pkt_cnt: payload.pkt_cnt,
+                last_pkt_cnt: payload.pkt_cnt,
+                nframes: 0,
+                nsynthesized: 0,
+                start_utc,
+                end_utc: String::new(),
+                compression_ratio: None,
+            },
+        });
+        self.seq += 1;
+        Ok(())
+    }
+
+    fn is_full(&self, seg: &Segment) -> bool {
+        let n = seg.info.nframes;
+        self.cfg.max_frames.is_some_and(|m| n >= m)
+            || self
+                .cfg
+                .max_bytes
+                .is_some_and(|m| n * N_BYTE_PER_FRAME as u64 >= m)
+    }
+
+    fn is_expired(&self, seg: &Segment) -> bool {
+        match (self.cfg.max_duration, seg.slot) {
+            (Some(d), Some(s)) => slot_of(&Utc::now(), d) != s,
+            _ => false,
+        }
+    }
+
+    pub fn write_frame(&mut self, payload: &Payload) -> std::io::Result<()> {
+        if let Some(ref seg) = self.current
+            && self.is_expired(seg)
+        {
+            self.close()?;
+        }
+        if self.current.is_none() {
+            self.open(payload)?;
+        }
+
+        let seg = self.current.as_mut().unwrap();
+        seg.sink.write_frame(payload)?;
+        seg.info.last_pkt_cnt = payload.pkt_cnt;
+        seg.info.nframes += 1;
+        if payload.is_synthesized() {
+            seg.info.nsynthesized += 1;
+        }
+
+        if self.is_full(self.current.as_ref().unwrap()) {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    /// Completes the current file, the next frame goes to a new one
+    pub fn close(&mut self) -> std::io::Result<()> {
+        let Some(mut seg) = self.current.take() else {
+            return Ok(());
+        };
+        seg.info.compression_ratio = seg
+            .sink
+            .compression_stats()
+            .filter(|_| self.meta.compression != Compression::None || self.meta.requant.is_some())
+            .map(|s| s.ratio());
+        seg.sink.finish()?;
+        seg.info.end_utc = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+
+        let sidecar = sidecar_path(&seg.path);
+        let sidecar_temp = temp_path(&sidecar);
+        let mut f = File::create(&sidecar_temp)?;
+        serde_yaml::to_writer(&mut f, &seg.info)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        f.flush()?;
+
+        for (from, to) in FrameSink::output_paths(&seg.temp, self.format)
+            .into_iter()
+            .zip(FrameSink::output_paths(&seg.path, self.format))
+        {
+            std::fs::rename(from, to)?;
+        }
+        std::fs::rename(sidecar_temp, sidecar)?;
+        println!(
+            "{} closed, pkt_cnt {}..{}, started {}{}",
+            seg.path.display(),
+            seg.info.first_pkt_cnt,
+            seg.info.last_pkt_cnt,
+            seg.start.format("%H:%M:%S"),
+            seg.info
+                .compression_ratio
+                .map(|r| format!(", compression ratio {r:.2}"))
+                .unwrap_or_default()
+        );
+        Ok(())
+    }
+}
+
+impl Drop for RotatingSink {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
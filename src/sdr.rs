@@ -2,6 +2,7 @@ use std::{
     fs::File,
     net::{SocketAddrV4, UdpSocket},
     path::Path,
+    sync::Arc,
     thread::JoinHandle,
     time::Duration,
 };
@@ -13,7 +14,7 @@ use lockfree_object_pool::LinearOwnedReusable;
 
 
 use crate::{
-    ctrl_msg::{CmdReplySummary, CtrlMsg, send_cmd}, payload::Payload, pipeline::{RecvCmd, recv_pkt}
+    ctrl_msg::{CmdReplySummary, CtrlMsg, send_cmd}, payload::Payload, pipeline::{RecvCmd, RecvCounters, recv_pkt_counted}
 };
 
 pub struct SdrCtrl {
@@ -22,32 +23,36 @@ pub struct SdrCtrl {
 }
 
 impl SdrCtrl {
-    pub fn send_cmd(&self, cmd: CtrlMsg) -> CmdReplySummary {
+    pub fn send_cmd(&self, cmd: CtrlMsg) -> std::io::Result<CmdReplySummary> {
         send_cmd(
             cmd,
             &[self.remote_ctrl_addr],
             self.local_ctrl_addr,
             Some(Duration::from_secs(10)),
-            1,
+            0,
         )
     }
 
-    pub fn query(&self) -> CmdReplySummary {
+    pub fn query(&self) -> std::io::Result<CmdReplySummary> {
         let cmd = CtrlMsg::Query { msg_id: 0 };
         self.send_cmd(cmd)
     }
 
-    pub fn init_device<P: std::fmt::Debug + AsRef<Path>>(&self, file_path: P) {
-        let cmds: Vec<CtrlMsg> =
-            from_reader(File::open(file_path).expect("file not open")).expect("failed to load cmd");
+    pub fn init_device<P: std::fmt::Debug + AsRef<Path>>(
+        &self,
+        file_path: P,
+    ) -> std::io::Result<()> {
+        let cmds: Vec<CtrlMsg> = from_reader(File::open(file_path)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
         for cmd in cmds {
-            println!("sending cmd:");
-            println!("{:?}", cmd);
-            self.send_cmd(cmd);
+            self.send_cmd(cmd)?;
         }
+        Ok(())
     }
 
-    pub fn set_mixer_freq(&self, freq_mega_hz: f64, sync: u32) -> CmdReplySummary {
+    /// Sends `MixerSet` with `freq_mega_hz` negated, as the board expects; out of
+    /// (-2000, 2000) MHz is an error.
+    pub fn set_mixer_freq(&self, freq_mega_hz: f64, sync: u32) -> std::io::Result<CmdReplySummary> {
         if freq_mega_hz > -2000.0 && freq_mega_hz < 2000.0 {
             let cmd = CtrlMsg::MixerSet {
                 msg_id: 0,
@@ -56,36 +61,46 @@ impl SdrCtrl {
                 sync: sync,
             };
             self.send_cmd(cmd)
-        }else{
-            panic!()
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("mixer frequency {freq_mega_hz} MHz out of (-2000, 2000)"),
+            ))
         }
     }
 
-    pub fn stream_start(&self) -> CmdReplySummary {
+    pub fn set_bit_shift(&self, shift_bits: u32) -> std::io::Result<CmdReplySummary> {
+        let cmd = CtrlMsg::BitShift {
+            msg_id: 0,
+            shift_bits,
+        };
+        self.send_cmd(cmd)
+    }
+
+    pub fn stream_start(&self) -> std::io::Result<CmdReplySummary> {
         let cmd = CtrlMsg::StreamStart { msg_id: 0 };
         self.send_cmd(cmd)
     }
 
-    pub fn stream_stop(&self) -> CmdReplySummary {
-        println!("stopped");
+    pub fn stream_stop(&self) -> std::io::Result<CmdReplySummary> {
         let cmd = CtrlMsg::StreamStop { msg_id: 0 };
         self.send_cmd(cmd)
     }
 }
 
 pub struct Sdr {
-    rx_thread: Option<JoinHandle<()>>,
+    rx_threads: Vec<JoinHandle<()>>,
     pub ctrl: SdrCtrl,
+    /// one per payload address
+    pub counters: Vec<Arc<RecvCounters>>,
 }
 
 impl Drop for Sdr {
     fn drop(&mut self) {
-        eprintln!("dropped");
-        self.ctrl.stream_stop();
-        let h = self.rx_thread.take();
-        if let Some(h1) = h
-            && let Ok(()) = h1.join()
-        {}
+        let _ = self.ctrl.stream_stop();
+        for h in self.rx_threads.drain(..) {
+            let _ = h.join();
+        }
     }
 }
 
@@ -96,36 +111,71 @@ impl Sdr {
         local_ctrl_addr: SocketAddrV4,
         local_payload_addr: SocketAddrV4,
         init_file: P,
-    ) -> (Sdr, Receiver<LinearOwnedReusable<Payload>>, Sender<RecvCmd>) {
+    ) -> std::io::Result<(Sdr, Receiver<LinearOwnedReusable<Payload>>, Sender<RecvCmd>)> {
+        let (sdr, mut rx_payload, mut tx_recv_cmd) = Self::new_multi(
+            remote_ctrl_addr,
+            local_ctrl_addr,
+            &[local_payload_addr],
+            init_file,
+        )?;
+        Ok((sdr, rx_payload.remove(0), tx_recv_cmd.remove(0)))
+    }
+
+    /// Like `new`, receiving the payload of each port of the board on its own address
+    #[allow(clippy::type_complexity)]
+    pub fn new_multi<P: std::fmt::Debug + AsRef<Path>>(
+        remote_ctrl_addr: SocketAddrV4,
+        local_ctrl_addr: SocketAddrV4,
+        local_payload_addrs: &[SocketAddrV4],
+        init_file: P,
+    ) -> std::io::Result<(
+        Sdr,
+        Vec<Receiver<LinearOwnedReusable<Payload>>>,
+        Vec<Sender<RecvCmd>>,
+    )> {
         let ctrl = SdrCtrl {
             remote_ctrl_addr,
             local_ctrl_addr,
         };
 
-        println!("init file: {init_file:?}");
-        ctrl.init_device(init_file);
+        ctrl.init_device(init_file)?;
 
-        let payload_socket =
-            UdpSocket::bind(local_payload_addr).expect("failed to bind payload socket");
+        let payload_sockets = local_payload_addrs
+            .iter()
+            .map(UdpSocket::bind)
+            .collect::<std::io::Result<Vec<_>>>()?;
 
         send_cmd(
             CtrlMsg::StreamStop { msg_id: 0 },
             &[remote_ctrl_addr],
             local_ctrl_addr,
             Some(Duration::from_secs(10)),
-            1,
-        );
-        let (tx_payload, rx_payload) = bounded::<LinearOwnedReusable<Payload>>(8192);
-        let (tx_recv_cmd, rx_recv_cmd) = bounded::<RecvCmd>(32);
-        let rx_thread =
-            std::thread::spawn(|| recv_pkt(payload_socket.into(), tx_payload, rx_recv_cmd));
-        (
+            0,
+        )?;
+        let mut rx_threads = Vec::new();
+        let mut rx_payload = Vec::new();
+        let mut tx_recv_cmd = Vec::new();
+        let mut counters = Vec::new();
+        for payload_socket in payload_sockets {
+            let (tx_payload, rx) = bounded::<LinearOwnedReusable<Payload>>(8192);
+            let (tx_cmd, rx_recv_cmd) = bounded::<RecvCmd>(32);
+            let c = Arc::new(RecvCounters::default());
+            let c1 = c.clone();
+            rx_threads.push(std::thread::spawn(move || {
+                recv_pkt_counted(payload_socket.into(), tx_payload, rx_recv_cmd, &c1)
+            }));
+            rx_payload.push(rx);
+            tx_recv_cmd.push(tx_cmd);
+            counters.push(c);
+        }
+        Ok((
             Sdr {
-                rx_thread: Some(rx_thread),
+                rx_threads,
                 ctrl,
+                counters,
             },
             rx_payload,
             tx_recv_cmd,
-        )
+        ))
     }
 }
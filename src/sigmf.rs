@@ -0,0 +1,204 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    capture_file::{CaptureMeta, SampleFormat},
+    payload::{Payload, n_pt_per_frame},
+    utils::as_u8_slice,
+};
+
+pub const SIGMF_VERSION: &str = "1.0.0";
+
+impl SampleFormat {
+    /// `None` for formats SigMF has no datatype for; requantized frames are offset
+    /// binary codes behind a per frame step, not plain `ci8`
+    pub fn sigmf_datatype(&self) -> Option<&'static str> {
+        match self {
+            SampleFormat::Ci16Le => Some("ci16_le"),
+            SampleFormat::Ci8 | SampleFormat::Ci4 | SampleFormat::Ci2 => None,
+        }
+    }
+}
+
+/// `<base>.sigmf-data` and `<base>.sigmf-meta`, any extension of `path` is dropped
+pub fn sigmf_paths<P: AsRef<Path>>(path: P) -> (PathBuf, PathBuf) {
+    let base = path.as_ref().with_extension("").into_os_string();
+    let with_suffix = |suffix: &str| {
+        let mut p = base.clone();
+        p.push(suffix);
+        PathBuf::from(p)
+    };
+    (with_suffix(".sigmf-data"), with_suffix(".sigmf-meta"))
+}
+
+/// Writes frames as a SigMF recording.
+///
+/// The center frequency is taken as `-mixer_freq`, the sign convention of
+/// `SdrCtrl::set_mixer_freq`. A new capture segment is started whenever `pkt_cnt`
+/// jumps, and runs of synthesized frames are annotated as gaps. The meta file is
+/// written by `finish`, or when the writer is dropped.
+pub struct SigMfWriter {
+    data: BufWriter<File>,
+    meta_path: PathBuf,
+    global: Map<String, Value>,
+    center_freq: Option<f64>,
+    captures: Vec<Value>,
+    annotations: Vec<Value>,
+    nsamples: u64,
+    next_pkt_cnt: Option<u64>,
+    gap: Option<(u64, u64)>,
+    finished: bool,
+}
+
+impl SigMfWriter {
+    pub fn create<P: AsRef<Path>>(path: P, meta: &CaptureMeta) -> std::io::Result<Self> {
+        let (data_path, meta_path) = sigmf_paths(path);
+        let mut global = Map::new();
+        global.insert(
+            "core:datatype".into(),
+            meta.sample_format
+                .sigmf_datatype()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("no SigMF datatype for {:?}", meta.sample_format),
+                    )
+                })?
+                .into(),
+        );
+        global.insert("core:version".into(), SIGMF_VERSION.into());
+        global.insert("core:recorder".into(), "syncdaq".into());
+        if let Some(fs) = meta.smp_rate {
+            global.insert("core:sample_rate".into(), fs.into());
+        }
+        if let Some(ref d) = meta.device {
+            global.insert("core:hw".into(), d.clone().into());
+        }
+        global.insert(
+            "core:extensions".into(),
+            json!([{"name": "syncdaq", "version": env!("CARGO_PKG_VERSION"), "optional": true}]),
+        );
+        if let Some(b) = meta.bit_shift {
+            global.insert("syncdaq:bit_shift".into(), b.into());
+        }
+        if let Some(c) = meta.clk_src {
+            global.insert("syncdaq:clk_src".into(), c.into());
+        }
+        if let Some(p) = meta.pps_src {
+            global.insert("syncdaq:pps_src".into(), p.into());
+        }
+        if let Some(f) = meta.mixer_freq {
+            global.insert("syncdaq:mixer_freq".into(), f.into());
+        }
+
+        let start_utc = meta
+            .start_utc
+            .clone()
+            .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true));
+        let center_freq = meta.mixer_freq.map(|f| -f * 1e6);
+        let mut first_capture = Map::new();
+        first_capture.insert("core:sample_start".into(), 0.into());
+        first_capture.insert("core:datetime".into(), start_utc.into());
+        if let Some(f) = center_freq {
+            first_capture.insert("core:frequency".into(), f.into());
+        }
+
+        Ok(Self {
+            data: BufWriter::new(File::create(data_path)?),
+            meta_path,
+            global,
+            center_freq,
+            captures: vec![Value::Object(first_capture)],
+            annotations: Vec::new(),
+            nsamples: 0,
+            next_pkt_cnt: None,
+            gap: None,
+            finished: false,
+        })
+    }
+
+    pub fn nsamples(&self) -> u64 {
+        self.nsamples
+    }
+
+    pub fn write_frame(&mut self, payload: &Payload) -> std::io::Result<()> {
+        let npt = n_pt_per_frame::<i16>() as u64;
+        match self.next_pkt_cnt {
+            None => {
+                self.captures[0]["syncdaq:pkt_cnt"] = payload.pkt_cnt.into();
+            }
+            Some(c) if c != payload.pkt_cnt => {
+                self.close_gap();
+                let mut capture = Map::new();
+                capture.insert("core:sample_start".into(), self.nsamples.into());
+                if let Some(f) = self.center_freq {
+                    capture.insert("core:frequency".into(), f.into());
+                }
+                capture.insert("syncdaq:pkt_cnt".into(), payload.pkt_cnt.into());
+                self.captures.push(Value::Object(capture));
+            }
+            _ => {}
+        }
+        self.next_pkt_cnt = Some(payload.pkt_cnt + 1);
+
+        if payload.is_synthesized() {
+            let (_, count) = self.gap.get_or_insert((self.nsamples, 0));
+            *count += npt;
+        } else {
+            self.close_gap();
+        }
+
+        self.data.write_all(as_u8_slice(&payload.data))?;
+        self.nsamples += npt;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.data.flush()
+    }
+
+    fn close_gap(&mut self) {
+        if let Some((start, count)) = self.gap.take() {
+            self.annotations.push(json!({
+                "core:sample_start": start,
+                "core:sample_count": count,
+                "core:label": "gap",
+                "core:comment": "frames lost on the network, zero filled",
+            }));
+        }
+    }
+
+    fn write_meta(&mut self) -> std::io::Result<()> {
+        self.close_gap();
+        let meta = json!({
+            "global": self.global,
+            "captures": self.captures,
+            "annotations": self.annotations,
+        });
+        let mut f = BufWriter::new(File::create(&self.meta_path)?);
+        serde_json::to_writer_pretty(&mut f, &meta)?;
+        f.flush()
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.data.flush()?;
+        self.write_meta()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for SigMfWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.data.flush();
+            let _ = self.write_meta();
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use crossbeam::channel::{Receiver, Sender};
+use lockfree_object_pool::LinearOwnedReusable;
+use num::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use crate::{payload::Payload, utils::as_complex_t};
+
+#[derive(Clone, Debug)]
+pub struct SpectrometerCfg {
+    pub nch: usize,
+    /// number of spectra M accumulated into S1/S2 for one SK estimate
+    pub sk_len: usize,
+    /// number of SK blocks per integration
+    pub nblocks: usize,
+    /// SK thresholds are 1 -/+ sk_nsigma*2/sqrt(M)
+    pub sk_nsigma: f64,
+}
+
+impl Default for SpectrometerCfg {
+    fn default() -> Self {
+        Self {
+            nch: 1024,
+            sk_len: 256,
+            nblocks: 64,
+            sk_nsigma: 3.0,
+        }
+    }
+}
+
+impl SpectrometerCfg {
+    pub fn sk_thresholds(&self) -> (f64, f64) {
+        let sigma = 2.0 / (self.sk_len as f64).sqrt();
+        (1.0 - self.sk_nsigma * sigma, 1.0 + self.sk_nsigma * sigma)
+    }
+
+    pub fn nsamples_per_integration(&self) -> usize {
+        self.nch * self.sk_len * self.nblocks
+    }
+}
+
+/// One integration; channel 0 is the most negative frequency.
+///
+/// `sk` and `mask` are `nblocks x nch`, row major. Flagged blocks are left out of
+/// `spectrum`, `nvalid` counts the blocks that went into each channel.
+#[derive(Clone, Debug)]
+pub struct IntegratedSpectrum {
+    pub port_id: u32,
+    pub first_pkt_cnt: u64,
+    pub last_pkt_cnt: u64,
+    pub nframes: usize,
+    pub nvalid_frames: usize,
+    pub nch: usize,
+    pub nblocks: usize,
+    pub spectrum: Vec<f64>,
+    pub nvalid: Vec<u32>,
+    pub sk: Vec<f64>,
+    pub mask: Vec<bool>,
+}
+
+impl IntegratedSpectrum {
+    pub fn is_flagged(&self, block: usize, ch: usize) -> bool {
+        self.mask[block * self.nch + ch]
+    }
+
+    pub fn flag_fraction(&self, ch: usize) -> f64 {
+        (0..self.nblocks)
+            .filter(|&b| self.is_flagged(b, ch))
+            .count() as f64
+            / self.nblocks as f64
+    }
+}
+
+pub fn spectral_kurtosis(s1: f64, s2: f64, m: usize) -> f64 {
+    let m = m as f64;
+    if s1 <= 0.0 {
+        return f64::NAN;
+    }
+    (m + 1.0) / (m - 1.0) * (m * s2 / (s1 * s1) - 1.0)
+}
+
+pub struct Spectrometer {
+    cfg: SpectrometerCfg,
+    fft: Arc<dyn Fft<f32>>,
+    buf: Vec<Complex<f32>>,
+    nbuf: usize,
+    s1: Vec<f64>,
+    s2: Vec<f64>,
+    nspec: usize,
+    sum: Vec<f64>,
+    nvalid: Vec<u32>,
+    sk: Vec<f64>,
+    mask: Vec<bool>,
+    nblocks: usize,
+    port_id: u32,
+    first_pkt_cnt: Option<u64>,
+    last_pkt_cnt: u64,
+    nframes: usize,
+    nvalid_frames: usize,
+}
+
+impl Spectrometer {
+    pub fn new(cfg: SpectrometerCfg) -> Self {
+        let nch = cfg.nch;
+        let fft = FftPlanner::new().plan_fft_forward(nch);
+        Self {
+            fft,
+            buf: vec![Complex::default(); nch],
+            nbuf: 0,
+            s1: vec![0.0; nch],
+            s2: vec![0.0; nch],
+            nspec: 0,
+            sum: vec![0.0; nch],
+            nvalid: vec![0; nch],
+            sk: Vec::with_capacity(nch * cfg.nblocks),
+            mask: Vec::with_capacity(nch * cfg.nblocks),
+            nblocks: 0,
+            port_id: 0,
+            first_pkt_cnt: None,
+            last_pkt_cnt: 0,
+            nframes: 0,
+            nvalid_frames: 0,
+            cfg,
+        }
+    }
+
+    pub fn cfg(&self) -> &SpectrometerCfg {
+        &self.cfg
+    }
+
+    /// Feeds one frame, returns the integrations it completed, oldest first.
+    ///
+    /// Synthesized frames carry no data; they are counted but break the
+    /// sample continuity, so a partially filled FFT buffer is dropped.
+    pub fn feed(&mut self, payload: &Payload) -> Vec<IntegratedSpectrum> {
+        if self.first_pkt_cnt.is_none() {
+            self.first_pkt_cnt = Some(payload.pkt_cnt);
+            self.port_id = payload.port_id;
+        }
+        self.last_pkt_cnt = payload.pkt_cnt;
+        self.nframes += 1;
+
+        if payload.is_synthesized() {
+            self.nbuf = 0;
+            return Vec::new();
+        }
+        self.nvalid_frames += 1;
+
+        let mut result = Vec::new();
+        let nch = self.cfg.nch;
+        let frame = as_complex_t::<i16>(&payload.data);
+        for (i, x) in frame.iter().enumerate() {
+            self.buf[self.nbuf] = Complex::new(x.re as f32, x.im as f32);
+            self.nbuf += 1;
+            if self.nbuf == nch {
+                self.nbuf = 0;
+                self.process_spectrum();
+                if self.nblocks == self.cfg.nblocks {
+                    result.push(self.take());
+                    if i + 1 < frame.len() {
+                        // the rest of this frame starts the next integration
+                        self.first_pkt_cnt = Some(payload.pkt_cnt);
+                        self.nframes = 1;
+                        self.nvalid_frames = 1;
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn process_spectrum(&mut self) {
+        self.fft.process(&mut self.buf);
+        for ((x, s1), s2) in self.buf.iter().zip(&mut self.s1).zip(&mut self.s2) {
+            let p = x.norm_sqr() as f64;
+            *s1 += p;
+            *s2 += p * p;
+        }
+        self.nspec += 1;
+        if self.nspec < self.cfg.sk_len {
+            return;
+        }
+
+        let (lo, hi) = self.cfg.sk_thresholds();
+        let nch = self.cfg.nch;
+        for i in 0..nch {
+            // fftshift so that the output starts from the most negative frequency
+            let k = (i + nch / 2) % nch;
+            let sk = spectral_kurtosis(self.s1[k], self.s2[k], self.cfg.sk_len);
+            let flagged = !(lo..=hi).contains(&sk);
+            self.sk.push(sk);
+            self.mask.push(flagged);
+            if !flagged {
+                self.sum[i] += self.s1[k] / self.cfg.sk_len as f64;
+                self.nvalid[i] += 1;
+            }
+        }
+        self.s1.fill(0.0);
+        self.s2.fill(0.0);
+        self.nspec = 0;
+        self.nblocks += 1;
+    }
+
+    fn take(&mut self) -> IntegratedSpectrum {
+        let nch = self.cfg.nch;
+        let spectrum = self
+            .sum
+            .iter()
+            .zip(&self.nvalid)
+            .map(|(&s, &n)| if n > 0 { s / n as f64 } else { 0.0 })
+            .collect();
+        let result = IntegratedSpectrum {
+            port_id: self.port_id,
+            first_pkt_cnt: self.first_pkt_cnt.unwrap_or(0),
+            last_pkt_cnt: self.last_pkt_cnt,
+            nframes: self.nframes,
+            nvalid_frames: self.nvalid_frames,
+            nch,
+            nblocks: self.nblocks,
+            spectrum,
+            nvalid: std::mem::replace(&mut self.nvalid, vec![0; nch]),
+            sk: std::mem::replace(&mut self.sk, Vec::with_capacity(nch * self.cfg.nblocks)),
+            mask: std::mem::replace(&mut self.mask, Vec::with_capacity(nch * self.cfg.nblocks)),
+        };
+        self.sum.fill(0.0);
+        self.nblocks = 0;
+        self.first_pkt_cnt = None;
+        self.nframes = 0;
+        self.nvalid_frames = 0;
+        result
+    }
+}
+
+pub fn run_spectrometer(
+    rx_payload: Receiver<LinearOwnedReusable<Payload>>,
+    tx_spec: Sender<IntegratedSpectrum>,
+    cfg: SpectrometerCfg,
+) {
+    let mut spec = Spectrometer::new(cfg);
+    while let Ok(payload) = rx_payload.recv() {
+        for s in spec.feed(&payload) {
+            if tx_spec.send(s).is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::UdpSocket,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crossbeam::channel::{Receiver, unbounded};
+use lockfree_object_pool::LinearOwnedReusable;
+
+use crate::{
+    capture_file::{CaptureMeta, OutputFormat},
+    payload::Payload,
+    rotate::{RotateCfg, RotatingSink},
+    utils::as_complex_t,
+};
+
+/// Decides, frame by frame, whether a capture should be triggered
+pub trait Trigger: Send {
+    /// Returns a description of the cause if `payload` triggers
+    fn check(&mut self, payload: &Payload) -> Option<String>;
+}
+
+/// Fires when the mean power of a frame exceeds `ratio` times the running baseline.
+///
+/// The baseline is an exponential average over frames that did not fire, synthesized
+/// frames are ignored.
+pub struct PowerTrigger {
+    pub ratio: f64,
+    pub alpha: f64,
+    /// frames averaged before the trigger is armed
+    pub warmup: usize,
+    baseline: Option<f64>,
+    nseen: usize,
+}
+
+impl PowerTrigger {
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio,
+            alpha: 1e-3,
+            warmup: 1000,
+            baseline: None,
+            nseen: 0,
+        }
+    }
+
+    pub fn baseline(&self) -> Option<f64> {
+        self.baseline
+    }
+}
+
+fn frame_power(payload: &Payload) -> f64 {
+    let data = as_complex_t::<i16>(&payload.data);
+    data.iter()
+        .map(|x| (x.re as f64).powi(2) + (x.im as f64).powi(2))
+        .sum::<f64>()
+        / data.len() as f64
+}
+
+impl Trigger for PowerTrigger {
+    fn check(&mut self, payload: &Payload) -> Option<String> {
+        if payload.is_synthesized() {
+            return None;
+        }
+        let p = frame_power(payload);
+        let Some(b) = self.baseline else {
+            self.baseline = Some(p);
+            self.nseen = 1;
+            return None;
+        };
+        if self.nseen >= self.warmup && b > 0.0 && p > self.ratio * b {
+            return Some(format!("power {:.2} x baseline", p / b));
+        }
+        self.baseline = Some(b + self.alpha.max(1.0 / (self.nseen + 1) as f64) * (p - b));
+        self.nseen += 1;
+        None
+    }
+}
+
+/// Fires on every frame with `pkt_cnt % period == offset`
+pub struct ScheduleTrigger {
+    pub period: u64,
+    pub offset: u64,
+}
+
+impl Trigger for ScheduleTrigger {
+    fn check(&mut self, payload: &Payload) -> Option<String> {
+        (self.period > 0 && payload.pkt_cnt % self.period == self.offset)
+            .then(|| "schedule".to_string())
+    }
+}
+
+/// Fires on the next frame after a datagram arrives on a UDP control socket.
+///
+/// The text of the datagram, e.g. `echo frb-123 | nc -u host port`, is used as the cause.
+pub struct ExternalTrigger {
+    rx: Receiver<String>,
+    /// ends the listening thread, which only looks at it between reads
+    stop: Arc<AtomicBool>,
+}
+
+impl ExternalTrigger {
+    pub fn listen(addr: &str) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+        let (tx, rx) = unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop1 = stop.clone();
+        std::thread::spawn(move || {
+            let mut buf = vec![0_u8; 1500];
+            while !stop1.load(Ordering::Relaxed) {
+                let (n, a) = match socket.recv_from(&mut buf) {
+                    Ok(r) => r,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                        ) =>
+                    {
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("external trigger stopped: {e}");
+                        return;
+                    }
+                };
+                let msg = String::from_utf8_lossy(&buf[..n]).trim().to_string();
+                if tx.send(format!("external from {a}: {msg}")).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Self { rx, stop })
+    }
+}
+
+impl Drop for ExternalTrigger {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Trigger for ExternalTrigger {
+    fn check(&mut self, _payload: &Payload) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TriggerEvent {
+    pub pkt_cnt: u64,
+    pub cause: String,
+}
+
+/// Keeps the last `npre` frames and dumps them, the triggering frame and the
+/// following `npost` frames into one file whenever a trigger fires.
+///
+/// A trigger during a dump extends it to `npost` frames after the new trigger.
+/// Files are named by `template` as in `rotate::expand_template`, and the cause is
+/// recorded in the `extra` field of the meta.
+pub struct TriggeredCapture {
+    ring: VecDeque<LinearOwnedReusable<Payload>>,
+    npre: usize,
+    npost: usize,
+    triggers: Vec<Box<dyn Trigger>>,
+    template: String,
+    meta: CaptureMeta,
+    format: OutputFormat,
+    seq: u64,
+    dump: Option<(RotatingSink, usize)>,
+}
+
+impl TriggeredCapture {
+    pub fn new(
+        npre: usize,
+        npost: usize,
+        template: &str,
+        meta: &CaptureMeta,
+        format: OutputFormat,
+    ) -> Self {
+        Self {
+            ring: VecDeque::with_capacity(npre + 1),
+            npre,
+            npost,
+            triggers: Vec::new(),
+            template: template.to_string(),
+            meta: meta.clone(),
+            format,
+            seq: 0,
+            dump: None,
+        }
+    }
+
+    pub fn add_trigger(&mut self, t: Box<dyn Trigger>) {
+        self.triggers.push(t);
+    }
+
+    pub fn is_dumping(&self) -> bool {
+        self.dump.is_some()
+    }
+
+    /// Feeds one frame, returns the event if a trigger fired on it
+    pub fn feed(
+        &mut self,
+        payload: LinearOwnedReusable<Payload>,
+    ) -> std::io::Result<Option<TriggerEvent>> {
+        // every trigger sees every frame, so their running state stays current
+        let causes: Vec<String> = self
+            .triggers
+            .iter_mut()
+            .filter_map(|t| t.check(&payload))
+            .collect();
+        let event = (!causes.is_empty()).then(|| TriggerEvent {
+            pkt_cnt: payload.pkt_cnt,
+            cause: causes.join("; "),
+        });
+
+        match (&mut self.dump, &event) {
+            (Some((_, remaining)), Some(_)) => *remaining = self.npost + 1,
+            (None, Some(ev)) => {
+                let mut meta = self.meta.clone();
+                meta.extra
+                    .insert("trigger_pkt_cnt".to_string(), ev.pkt_cnt.to_string());
+                meta.extra
+                    .insert("trigger_cause".to_string(), ev.cause.clone());
+                let cfg = RotateCfg {
+                    template: self.template.clone(),
+                    ..Default::default()
+                };
+                let mut sink = RotatingSink::new(cfg, &meta, self.format);
+                sink.set_seq(self.seq);
+                self.seq += 1;
+                for p in self.ring.drain(..) {
+                    sink.write_frame(&p)?;
+                }
+                self.dump = Some((sink, self.npost + 1));
+            }
+            _ => {}
+        }
+
+        if let Some((sink, remaining)) = self.dump.as_mut() {
+            sink.write_frame(&payload)?;
+            *remaining -= 1;
+            if *remaining == 0 {
+                sink.close()?;
+                self.dump = None;
+            }
+        } else if self.npre > 0 {
+            if self.ring.len() == self.npre {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(payload);
+        }
+        Ok(event)
+    }
+
+    /// Completes a dump in progress
+    pub fn close(&mut self) -> std::io::Result<()> {
+        if let Some((mut sink, _)) = self.dump.take() {
+            sink.close()?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    net::UdpSocket,
+    net::{Ipv4Addr, UdpSocket},
     os::fd::AsRawFd,
     slice::{from_raw_parts, from_raw_parts_mut},
 };
@@ -11,7 +11,7 @@ use serde::{Deserializer,Serializer};
 use std::fmt;
 
 
-use libc::{SO_RCVBUF, SOL_SOCKET, setsockopt, socklen_t};
+use libc::{IP_MULTICAST_IF, IPPROTO_IP, SO_RCVBUF, SO_SNDBUF, SOL_SOCKET, setsockopt, socklen_t};
 
 pub fn as_complex_t<'a, 'b, T:Sized>(input: &'a[u8])->&'b[Complex<T>]
 where 
@@ -62,6 +62,52 @@ pub fn set_recv_buffer_size(socket: &UdpSocket, size: usize) -> std::io::Result<
 }
 
 
+pub fn set_send_buffer_size(socket: &UdpSocket, size: usize) -> std::io::Result<()> {
+    let fd = socket.as_raw_fd();
+    let size = size as libc::c_int;
+
+    let ret = unsafe {
+        setsockopt(
+            fd,
+            SOL_SOCKET,
+            SO_SNDBUF,
+            &size as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&size) as socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Selects the local interface, by its address, that multicast datagrams are sent from
+pub fn set_multicast_if_v4(socket: &UdpSocket, iface: Ipv4Addr) -> std::io::Result<()> {
+    let fd = socket.as_raw_fd();
+    let addr = libc::in_addr {
+        s_addr: u32::from(iface).to_be(),
+    };
+
+    let ret = unsafe {
+        setsockopt(
+            fd,
+            IPPROTO_IP,
+            IP_MULTICAST_IF,
+            &addr as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&addr) as socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+
 pub mod u8_hex_array {
     use super::*;
 
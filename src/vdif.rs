@@ -0,0 +1,445 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+
+use crate::{
+    capture_file::CaptureMeta,
+    payload::{Payload, n_pt_per_frame},
+    requant::RunningRms,
+    utils::as_complex_t,
+};
+
+pub const VDIF_HEADER_SIZE: usize = 32;
+
+/// optimal 2-bit threshold in units of sigma
+pub const VDIF_2BIT_THRESHOLD: f64 = 0.9816;
+
+/// sigma of the 8-bit output in quantization levels
+pub const VDIF_8BIT_SIGMA: f64 = 20.0;
+
+#[derive(Clone, Debug)]
+pub struct VdifCfg {
+    /// bits per component: 2, 8 or 16
+    pub bits: u32,
+    pub station_id: u16,
+    /// UTC of `pkt_cnt` 0, must be on a whole second
+    pub pkt_cnt0_utc: DateTime<Utc>,
+    /// in Hz, must be a multiple of the samples per frame
+    pub smp_rate: u64,
+    /// input sigma used for requantization; a running RMS per thread if `None`
+    pub fixed_sigma: Option<f64>,
+}
+
+impl VdifCfg {
+    /// Takes the epoch, the sample rate and the station from `meta`
+    pub fn from_meta(meta: &CaptureMeta, bits: u32) -> std::io::Result<Self> {
+        let invalid =
+            |s: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, s.to_string());
+        let pkt_cnt0_utc = meta
+            .pkt_cnt0_utc
+            .as_ref()
+            .ok_or_else(|| invalid("vdif needs the utc of pkt_cnt 0"))?;
+        let pkt_cnt0_utc = DateTime::parse_from_rfc3339(pkt_cnt0_utc)
+            .map_err(|_| invalid("invalid utc of pkt_cnt 0"))?
+            .with_timezone(&Utc);
+        let smp_rate = meta
+            .smp_rate
+            .ok_or_else(|| invalid("vdif needs the sample rate"))?;
+        let station_id = meta
+            .station
+            .as_ref()
+            .map(|s| {
+                s.bytes()
+                    .take(2)
+                    .fold(0_u16, |acc, b| (acc << 8) | b as u16)
+            })
+            .unwrap_or(0);
+        let cfg = Self {
+            bits,
+            station_id,
+            pkt_cnt0_utc,
+            smp_rate: smp_rate.round() as u64,
+            fixed_sigma: None,
+        };
+        cfg.check()?;
+        Ok(cfg)
+    }
+
+    pub fn check(&self) -> std::io::Result<()> {
+        let invalid =
+            |s: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, s.to_string());
+        if ![2, 8, 16].contains(&self.bits) {
+            return Err(invalid("vdif bits must be 2, 8 or 16"));
+        }
+        if self.smp_rate == 0 || !self.smp_rate.is_multiple_of(n_pt_per_frame::<i16>() as u64) {
+            return Err(invalid(
+                "sample rate must be a multiple of the samples per frame",
+            ));
+        }
+        if self.pkt_cnt0_utc.timestamp_subsec_nanos() != 0 {
+            return Err(invalid("utc of pkt_cnt 0 must be on a whole second"));
+        }
+        ref_epoch(&self.pkt_cnt0_utc)?;
+        Ok(())
+    }
+
+    pub fn frame_size(&self) -> usize {
+        VDIF_HEADER_SIZE + n_pt_per_frame::<i16>() * 2 * self.bits as usize / 8
+    }
+
+    pub fn frames_per_second(&self) -> u64 {
+        self.smp_rate / n_pt_per_frame::<i16>() as u64
+    }
+}
+
+/// Last reference epoch the 6 bits of the header can hold, in the second half of 2031
+pub const VDIF_MAX_REF_EPOCH: u32 = 0x3f;
+
+/// Start of the VDIF reference epoch containing `t`, and its index in half years since 2000.
+///
+/// Fails for times before 2000 or after the last epoch.
+pub fn ref_epoch(t: &DateTime<Utc>) -> std::io::Result<(DateTime<Utc>, u32)> {
+    let half = if t.month() >= 7 { 1 } else { 0 };
+    let index = (t.year() - 2000) * 2 + half;
+    if !(0..=VDIF_MAX_REF_EPOCH as i32).contains(&index) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{t} is outside the vdif reference epochs of 2000 to 2031"),
+        ));
+    }
+    let start = Utc
+        .with_ymd_and_hms(t.year(), 1 + 6 * half as u32, 1, 0, 0, 0)
+        .unwrap();
+    Ok((start, index as u32))
+}
+
+/// Largest seconds from the reference epoch the 30 bits of the header can hold
+pub const VDIF_MAX_SECONDS: u64 = 0x3fff_ffff;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VdifHeader {
+    pub invalid: bool,
+    pub seconds: u32,
+    pub ref_epoch: u32,
+    pub frame_no: u32,
+    pub log2_nchan: u32,
+    /// frame length in bytes, header included
+    pub frame_len: usize,
+    pub complex: bool,
+    pub bits: u32,
+    pub thread_id: u32,
+    pub station_id: u16,
+}
+
+impl VdifHeader {
+    pub fn to_words(&self) -> [u32; 8] {
+        let mut w = [0_u32; 8];
+        w[0] = ((self.invalid as u32) << 31) | (self.seconds & 0x3fff_ffff);
+        w[1] = ((self.ref_epoch & 0x3f) << 24) | (self.frame_no & 0xff_ffff);
+        w[2] = ((self.log2_nchan & 0x1f) << 24) | ((self.frame_len / 8) as u32 & 0xff_ffff);
+        w[3] = ((self.complex as u32) << 31)
+            | (((self.bits - 1) & 0x1f) << 26)
+            | ((self.thread_id & 0x3ff) << 16)
+            | self.station_id as u32;
+        w
+    }
+
+    pub fn from_words(w: &[u32; 8]) -> Self {
+        Self {
+            invalid: w[0] >> 31 != 0,
+            seconds: w[0] & 0x3fff_ffff,
+            ref_epoch: (w[1] >> 24) & 0x3f,
+            frame_no: w[1] & 0xff_ffff,
+            log2_nchan: (w[2] >> 24) & 0x1f,
+            frame_len: (w[2] & 0xff_ffff) as usize * 8,
+            complex: w[3] >> 31 != 0,
+            bits: ((w[3] >> 26) & 0x1f) + 1,
+            thread_id: (w[3] >> 16) & 0x3ff,
+            station_id: (w[3] & 0xffff) as u16,
+        }
+    }
+}
+
+fn quantize_2bit(x: f64, threshold: f64) -> u32 {
+    if x < -threshold {
+        0
+    } else if x < 0.0 {
+        1
+    } else if x < threshold {
+        2
+    } else {
+        3
+    }
+}
+
+fn quantize_8bit(x: f64, scale: f64) -> u32 {
+    ((x * scale).round().clamp(-128.0, 127.0) as i32 + 128) as u32
+}
+
+/// Packs complex i16 frames into VDIF frames, one per payload.
+///
+/// The thread ID is the `port_id`, synthesized frames are written with the invalid bit set.
+pub struct VdifWriter<W: Write> {
+    inner: W,
+    cfg: VdifCfg,
+    ref_epoch: u32,
+    epoch_offset: u64,
+    rms: RunningRms,
+    buf: Vec<u8>,
+    pub nframes: u64,
+    pub ninvalid: u64,
+}
+
+impl VdifWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, cfg: VdifCfg) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), cfg)
+    }
+}
+
+impl<W: Write> VdifWriter<W> {
+    pub fn new(inner: W, cfg: VdifCfg) -> std::io::Result<Self> {
+        cfg.check()?;
+        let (epoch_start, ref_epoch) = ref_epoch(&cfg.pkt_cnt0_utc)?;
+        let epoch_offset = (cfg.pkt_cnt0_utc - epoch_start).num_seconds() as u64;
+        let frame_size = cfg.frame_size();
+        Ok(Self {
+            inner,
+            cfg,
+            ref_epoch,
+            epoch_offset,
+            rms: RunningRms::default(),
+            buf: vec![0; frame_size],
+            nframes: 0,
+            ninvalid: 0,
+        })
+    }
+
+    /// Fails once the seconds since the reference epoch overflow the header
+    pub fn header_of(&self, payload: &Payload) -> std::io::Result<VdifHeader> {
+        let npt = n_pt_per_frame::<i16>() as u64;
+        let nsamples = payload.pkt_cnt * npt;
+        let seconds = self.epoch_offset + nsamples / self.cfg.smp_rate;
+        if seconds > VDIF_MAX_SECONDS {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "pkt_cnt {} is {seconds} s after the vdif reference epoch, more than the header holds",
+                    payload.pkt_cnt
+                ),
+            ));
+        }
+        Ok(VdifHeader {
+            invalid: payload.is_synthesized(),
+            seconds: seconds as u32,
+            ref_epoch: self.ref_epoch,
+            frame_no: ((nsamples % self.cfg.smp_rate) / npt) as u32,
+            log2_nchan: 0,
+            frame_len: self.cfg.frame_size(),
+            complex: true,
+            bits: self.cfg.bits,
+            thread_id: payload.port_id,
+            station_id: self.cfg.station_id,
+        })
+    }
+
+    fn sigma(&mut self, payload: &Payload) -> f64 {
+        self.cfg
+            .fixed_sigma
+            .unwrap_or_else(|| self.rms.sigma(payload))
+    }
+
+    pub fn write_frame(&mut self, payload: &Payload) -> std::io::Result<()> {
+        let header = self.header_of(payload)?;
+        let sigma = if header.invalid || self.cfg.bits == 16 {
+            1.0
+        } else {
+            self.sigma(payload)
+        };
+
+        for (i, w) in header.to_words().iter().enumerate() {
+            self.buf[i * 4..i * 4 + 4].copy_from_slice(&w.to_le_bytes());
+        }
+        let data = &mut self.buf[VDIF_HEADER_SIZE..];
+        data.fill(0);
+
+        if header.invalid {
+            self.ninvalid += 1;
+        } else {
+            let samples = as_complex_t::<i16>(&payload.data);
+            match self.cfg.bits {
+                2 => {
+                    let threshold = VDIF_2BIT_THRESHOLD * sigma;
+                    for (i, x) in samples.iter().enumerate() {
+                        // I in the lower bits, then Q
+                        let v = quantize_2bit(x.re as f64, threshold)
+                            | (quantize_2bit(x.im as f64, threshold) << 2);
+                        data[i / 2] |= (v << (4 * (i % 2))) as u8;
+                    }
+                }
+                8 => {
+                    let scale = VDIF_8BIT_SIGMA / sigma;
+                    for (i, x) in samples.iter().enumerate() {
+                        data[2 * i] = quantize_8bit(x.re as f64, scale) as u8;
+                        data[2 * i + 1] = quantize_8bit(x.im as f64, scale) as u8;
+                    }
+                }
+                _ => {
+                    for (i, x) in samples.iter().enumerate() {
+                        let re = (x.re as i32 + 32768) as u16;
+                        let im = (x.im as i32 + 32768) as u16;
+                        data[4 * i..4 * i + 2].copy_from_slice(&re.to_le_bytes());
+                        data[4 * i + 2..4 * i + 4].copy_from_slice(&im.to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        self.inner.write_all(&self.buf)?;
+        self.nframes += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(bits: u32) -> VdifCfg {
+        VdifCfg {
+            bits,
+            station_id: u16::from_be_bytes(*b"Sd"),
+            pkt_cnt0_utc: Utc.with_ymd_and_hms(2024, 8, 1, 0, 0, 0).unwrap(),
+            smp_rate: 1000 * n_pt_per_frame::<i16>() as u64,
+            fixed_sigma: Some(100.0),
+        }
+    }
+
+    /// frame with components `f(i)` for I and `-f(i)` for Q of sample `i`
+    fn payload(pkt_cnt: u64, f: impl Fn(usize) -> i16) -> Payload {
+        let mut p = Payload {
+            pkt_cnt,
+            port_id: 3,
+            ..Default::default()
+        };
+        for (i, x) in p.data.chunks_exact_mut(4).enumerate() {
+            x[..2].copy_from_slice(&f(i).to_le_bytes());
+            x[2..].copy_from_slice(&(-f(i)).to_le_bytes());
+        }
+        p
+    }
+
+    fn write(cfg: VdifCfg, p: &Payload) -> Vec<u8> {
+        let mut w = VdifWriter::new(Vec::new(), cfg).unwrap();
+        w.write_frame(p).unwrap();
+        assert_eq!(w.inner.len(), w.cfg.frame_size());
+        w.inner
+    }
+
+    fn header_of(frame: &[u8]) -> VdifHeader {
+        let mut w = [0_u32; 8];
+        for (w, b) in w.iter_mut().zip(frame.chunks_exact(4)) {
+            *w = u32::from_le_bytes(b.try_into().unwrap());
+        }
+        VdifHeader::from_words(&w)
+    }
+
+    #[test]
+    fn header_words_round_trip() {
+        let h = VdifHeader {
+            invalid: true,
+            seconds: VDIF_MAX_SECONDS as u32,
+            ref_epoch: VDIF_MAX_REF_EPOCH,
+            frame_no: 0xab_cdef,
+            log2_nchan: 5,
+            frame_len: 8 * 0x12_3456,
+            complex: true,
+            bits: 2,
+            thread_id: 0x3ff,
+            station_id: 0xbeef,
+        };
+        assert_eq!(VdifHeader::from_words(&h.to_words()), h);
+        let h = VdifHeader {
+            bits: 16,
+            frame_len: VDIF_HEADER_SIZE,
+            ..Default::default()
+        };
+        assert_eq!(VdifHeader::from_words(&h.to_words()), h);
+    }
+
+    #[test]
+    fn reference_epochs() {
+        let at = |y, m| Utc.with_ymd_and_hms(y, m, 15, 12, 0, 0).unwrap();
+        let (start, index) = ref_epoch(&at(2024, 8)).unwrap();
+        assert_eq!(
+            (start, index),
+            (Utc.with_ymd_and_hms(2024, 7, 1, 0, 0, 0).unwrap(), 49)
+        );
+        assert_eq!(ref_epoch(&at(2000, 1)).unwrap().1, 0);
+        assert_eq!(ref_epoch(&at(2031, 12)).unwrap().1, VDIF_MAX_REF_EPOCH);
+        assert!(ref_epoch(&at(2032, 1)).is_err());
+        assert!(ref_epoch(&at(1999, 12)).is_err());
+    }
+
+    #[test]
+    fn header_of_payload() {
+        let w = VdifWriter::new(Vec::new(), cfg(8)).unwrap();
+        let h = w.header_of(&payload(2500, |_| 0)).unwrap();
+        // 31 days of July before pkt_cnt 0
+        assert_eq!(
+            (h.ref_epoch, h.seconds, h.frame_no),
+            (49, 31 * 86400 + 2, 500)
+        );
+        assert_eq!((h.thread_id, h.station_id, h.bits), (3, 0x5364, 8));
+        assert!(h.complex && !h.invalid);
+
+        // the 30 bits of seconds overflow
+        let pkt_cnt = (VDIF_MAX_SECONDS + 1) * w.cfg.frames_per_second();
+        assert!(w.header_of(&payload(pkt_cnt, |_| 0)).is_err());
+    }
+
+    #[test]
+    fn two_bit_coding() {
+        // thresholds at 0 and +-0.9816 sigma
+        let levels = [-200, -50, 50, 200];
+        let frame = write(cfg(2), &payload(0, |i| levels[i % 4]));
+        let h = header_of(&frame);
+        assert_eq!((h.bits, h.frame_len), (2, VDIF_HEADER_SIZE + 1024));
+        for (i, &b) in frame[VDIF_HEADER_SIZE..].iter().enumerate() {
+            for k in 0..2 {
+                let code = (b >> (4 * k)) & 0xf;
+                let j = (2 * i + k) % 4;
+                // I in the lower bits, Q = -I the mirrored code
+                assert_eq!(code & 3, j as u8);
+                assert_eq!(code >> 2, 3 - j as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn eight_bit_coding() {
+        // 20 levels per sigma, offset binary, clipped
+        let values = [0, 5, -5, 100, 1000];
+        let frame = write(cfg(8), &payload(0, |i| values[i % 5]));
+        let expected = [(128, 128), (129, 127), (127, 129), (148, 108), (255, 0)];
+        for (i, x) in frame[VDIF_HEADER_SIZE..].chunks_exact(2).enumerate() {
+            assert_eq!((x[0], x[1]), expected[i % 5]);
+        }
+    }
+
+    #[test]
+    fn synthesized_frames_are_invalid() {
+        let mut p = payload(0, |_| 1000);
+        p.mark_synthesized();
+        let frame = write(cfg(2), &p);
+        assert!(header_of(&frame).invalid);
+        assert!(frame[VDIF_HEADER_SIZE..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,357 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use crossbeam::channel::Receiver;
+use lockfree_object_pool::LinearOwnedReusable;
+use num::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+use crate::{payload::Payload, utils::as_complex_t};
+
+/// Result of cross-correlating stream `y` against reference stream `x`.
+///
+/// A positive delay means `y` lags `x`, i.e. `y[t] ~ x[t - delay]`.
+#[derive(Clone, Debug)]
+pub struct DelayEstimate {
+    pub nch: usize,
+    pub nseg: usize,
+    pub int_delay: isize,
+    pub frac_delay: f64,
+    /// phase of `y` relative to `x` at zero frequency, in rad
+    pub phase_offset: f64,
+    /// normalized frequency of each channel, in cycles per sample, fft order
+    pub freq: Vec<f64>,
+    /// measured phase of the averaged cross spectrum, in rad
+    pub phase: Vec<f64>,
+    /// coherence |<Y X*>| / sqrt(<|X|^2><|Y|^2>) of each channel
+    pub coherence: Vec<f64>,
+}
+
+/// Per-channel complex weights that align `y` to `x` when multiplied onto the spectrum of `y`
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Correction {
+    pub int_delay: isize,
+    pub frac_delay: f64,
+    pub phase_offset: f64,
+    pub weights: Vec<[f64; 2]>,
+}
+
+impl DelayEstimate {
+    pub fn total_delay(&self) -> f64 {
+        self.int_delay as f64 + self.frac_delay
+    }
+
+    pub fn mean_coherence(&self) -> f64 {
+        self.coherence.iter().sum::<f64>() / self.nch as f64
+    }
+
+    /// phase predicted by the fitted delay and phase offset model
+    pub fn model_phase(&self, ch: usize) -> f64 {
+        wrap_phase(self.phase_offset - 2.0 * PI * self.freq[ch] * self.total_delay())
+    }
+
+    pub fn is_aligned(&self, delay_tol: f64, phase_tol: f64) -> bool {
+        self.total_delay().abs() <= delay_tol && self.phase_offset.abs() <= phase_tol
+    }
+
+    pub fn correction(&self) -> Correction {
+        let weights = (0..self.nch)
+            .map(|ch| {
+                let w = Complex::from_polar(1.0, -self.model_phase(ch));
+                [w.re, w.im]
+            })
+            .collect();
+        Correction {
+            int_delay: self.int_delay,
+            frac_delay: self.frac_delay,
+            phase_offset: self.phase_offset,
+            weights,
+        }
+    }
+}
+
+pub fn wrap_phase(x: f64) -> f64 {
+    let y = (x + PI).rem_euclid(2.0 * PI) - PI;
+    if y <= -PI { y + 2.0 * PI } else { y }
+}
+
+pub fn channel_freq(nch: usize) -> Vec<f64> {
+    (0..nch)
+        .map(|k| {
+            if k < nch.div_ceil(2) {
+                k as f64 / nch as f64
+            } else {
+                k as f64 / nch as f64 - 1.0
+            }
+        })
+        .collect()
+}
+
+pub struct CrossCorrelator {
+    nch: usize,
+    fft: Arc<dyn Fft<f64>>,
+    ifft: Arc<dyn Fft<f64>>,
+    cross: Vec<Complex<f64>>,
+    pxx: Vec<f64>,
+    pyy: Vec<f64>,
+    nseg: usize,
+    buf_x: Vec<Complex<f64>>,
+    buf_y: Vec<Complex<f64>>,
+}
+
+impl CrossCorrelator {
+    pub fn new(nch: usize) -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(nch);
+        let ifft = planner.plan_fft_inverse(nch);
+        Self {
+            nch,
+            fft,
+            ifft,
+            cross: vec![Complex::default(); nch],
+            pxx: vec![0.0; nch],
+            pyy: vec![0.0; nch],
+            nseg: 0,
+            buf_x: vec![Complex::default(); nch],
+            buf_y: vec![Complex::default(); nch],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.cross.fill(Complex::default());
+        self.pxx.fill(0.0);
+        self.pyy.fill(0.0);
+        self.nseg = 0;
+    }
+
+    pub fn nseg(&self) -> usize {
+        self.nseg
+    }
+
+    /// Averaged cross spectrum <Y X*> in fft order
+    pub fn visibility(&self) -> Vec<Complex<f64>> {
+        let n = self.nseg.max(1) as f64;
+        self.cross.iter().map(|c| c / n).collect()
+    }
+
+    /// Accumulates every complete `nch` segment of the two equally long inputs
+    pub fn feed<T>(&mut self, x: &[Complex<T>], y: &[Complex<T>])
+    where
+        T: Copy + Into<f64>,
+    {
+        assert_eq!(x.len(), y.len());
+        for (sx, sy) in x.chunks_exact(self.nch).zip(y.chunks_exact(self.nch)) {
+            for (d, s) in self.buf_x.iter_mut().zip(sx) {
+                *d = Complex::new(s.re.into(), s.im.into());
+            }
+            for (d, s) in self.buf_y.iter_mut().zip(sy) {
+                *d = Complex::new(s.re.into(), s.im.into());
+            }
+            self.fft.process(&mut self.buf_x);
+            self.fft.process(&mut self.buf_y);
+            for k in 0..self.nch {
+                let (a, b) = (self.buf_x[k], self.buf_y[k]);
+                self.cross[k] += b * a.conj();
+                self.pxx[k] += a.norm_sqr();
+                self.pyy[k] += b.norm_sqr();
+            }
+            self.nseg += 1;
+        }
+    }
+
+    pub fn estimate(&self) -> DelayEstimate {
+        let nch = self.nch;
+        let freq = channel_freq(nch);
+
+        // coarse delay from the peak of the lag spectrum
+        let mut lag = self.cross.clone();
+        self.ifft.process(&mut lag);
+        let (m0, _) = lag.iter().enumerate().fold((0, -1.0), |(im, vm), (i, v)| {
+            if v.norm() > vm {
+                (i, v.norm())
+            } else {
+                (im, vm)
+            }
+        });
+        let int_delay = if m0 < nch.div_ceil(2) {
+            m0 as isize
+        } else {
+            m0 as isize - nch as isize
+        };
+
+        // residual delay and phase from the cross spectrum with the coarse delay removed
+        let residual: Vec<_> = self
+            .cross
+            .iter()
+            .zip(&freq)
+            .map(|(c, &f)| c * Complex::from_polar(1.0, 2.0 * PI * f * int_delay as f64))
+            .collect();
+
+        // |frac_delay|<=0.5, so the residual phase stays within +-pi/2 of the offset
+        // and a weighted linear fit needs no unwrapping
+        let mut phase_offset = residual.iter().sum::<Complex<f64>>().arg();
+        let mut frac_delay = 0.0;
+        for _ in 0..2 {
+            let (mut sw, mut sf, mut sp, mut sff, mut sfp) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for (c, &f) in residual.iter().zip(&freq) {
+                let w = c.norm();
+                let p =
+                    (c * Complex::from_polar(1.0, 2.0 * PI * f * frac_delay - phase_offset)).arg();
+                sw += w;
+                sf += w * f;
+                sp += w * p;
+                sff += w * f * f;
+                sfp += w * f * p;
+            }
+            let det = sw * sff - sf * sf;
+            if sw == 0.0 || det == 0.0 {
+                break;
+            }
+            let slope = (sw * sfp - sf * sp) / det;
+            let intercept = (sp - slope * sf) / sw;
+            frac_delay += -slope / (2.0 * PI);
+            phase_offset = wrap_phase(phase_offset + intercept);
+        }
+
+        let phase = self.cross.iter().map(|c| c.arg()).collect();
+        let coherence = self
+            .cross
+            .iter()
+            .zip(self.pxx.iter().zip(&self.pyy))
+            .map(|(c, (&px, &py))| {
+                if px > 0.0 && py > 0.0 {
+                    c.norm() / (px * py).sqrt()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+        DelayEstimate {
+            nch,
+            nseg: self.nseg,
+            int_delay,
+            frac_delay,
+            phase_offset,
+            freq,
+            phase,
+            coherence,
+        }
+    }
+}
+
+pub fn estimate_delay<T>(x: &[Complex<T>], y: &[Complex<T>], nch: usize) -> DelayEstimate
+where
+    T: Copy + Into<f64>,
+{
+    let mut xc = CrossCorrelator::new(nch);
+    xc.feed(x, y);
+    xc.estimate()
+}
+
+/// Receives from two payload streams until `nframes` pairs with matching `pkt_cnt` are
+/// collected, also returns the `pkt_cnt` of the first and the last pair
+#[allow(clippy::type_complexity)]
+pub fn collect_aligned(
+    rx_x: &Receiver<LinearOwnedReusable<Payload>>,
+    rx_y: &Receiver<LinearOwnedReusable<Payload>>,
+    nframes: usize,
+) -> (Vec<Complex<i16>>, Vec<Complex<i16>>, (u64, u64)) {
+    let mut x = Vec::new();
+    let mut y = Vec::new();
+    let mut first_pkt_cnt = None;
+    let mut px = rx_x.recv().expect("failed to recv payload");
+    let mut py = rx_y.recv().expect("failed to recv payload");
+    let mut n = 0;
+    while n < nframes {
+        if px.pkt_cnt < py.pkt_cnt {
+            px = rx_x.recv().expect("failed to recv payload");
+        } else if py.pkt_cnt < px.pkt_cnt {
+            py = rx_y.recv().expect("failed to recv payload");
+        } else {
+            first_pkt_cnt.get_or_insert(px.pkt_cnt);
+            x.extend_from_slice(as_complex_t::<i16>(&px.data));
+            y.extend_from_slice(as_complex_t::<i16>(&py.data));
+            n += 1;
+            if n < nframes {
+                px = rx_x.recv().expect("failed to recv payload");
+                py = rx_y.recv().expect("failed to recv payload");
+            }
+        }
+    }
+    (x, y, (first_pkt_cnt.unwrap_or(px.pkt_cnt), px.pkt_cnt))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+    use rand_distr::{Distribution, Normal};
+
+    use super::*;
+    use crate::payload::n_pt_per_frame;
+
+    /// Two frames of gaussian noise, `y` delayed by `delay` samples and turned by `phase`
+    fn noise_pair(
+        nframes: usize,
+        delay: f64,
+        phase: f64,
+    ) -> (Vec<Complex<i16>>, Vec<Complex<i16>>) {
+        let n = nframes * n_pt_per_frame::<i16>();
+        let mut rng = StdRng::seed_from_u64(26);
+        let normal = Normal::new(0.0, 1000.0).unwrap();
+        let mut x: Vec<Complex<f64>> = (0..n)
+            .map(|_| Complex::new(normal.sample(&mut rng), normal.sample(&mut rng)))
+            .collect();
+
+        // delay in the frequency domain so fractional delays are exact, up to the wrap around
+        let mut planner = FftPlanner::new();
+        planner.plan_fft_forward(n).process(&mut x);
+        let mut y: Vec<_> = x
+            .iter()
+            .zip(channel_freq(n))
+            .map(|(c, f)| c * Complex::from_polar(1.0, phase - 2.0 * PI * f * delay))
+            .collect();
+        let ifft = planner.plan_fft_inverse(n);
+        ifft.process(&mut x);
+        ifft.process(&mut y);
+        let to_i16 = |v: Vec<Complex<f64>>| {
+            v.iter()
+                .map(|c| {
+                    Complex::new(
+                        (c.re / n as f64).round() as i16,
+                        (c.im / n as f64).round() as i16,
+                    )
+                })
+                .collect()
+        };
+        (to_i16(x), to_i16(y))
+    }
+
+    #[test]
+    fn fractional_delay_and_phase() {
+        for (delay, phase) in [(3.3, 0.8), (-5.7, -2.5), (0.45, 3.0)] {
+            let (x, y) = noise_pair(8, delay, phase);
+            let mut xc = CrossCorrelator::new(256);
+            xc.feed(&x, &y);
+            let est = xc.estimate();
+            assert_eq!(est.nseg, x.len() / 256);
+            assert_eq!(est.int_delay, delay.round() as isize, "{est:?}");
+            assert!((est.total_delay() - delay).abs() < 0.02, "{delay} {est:?}");
+            assert!(
+                wrap_phase(est.phase_offset - phase).abs() < 0.02,
+                "{phase} {est:?}"
+            );
+            assert!(est.mean_coherence() > 0.9, "{est:?}");
+
+            // the correction turns the visibility back to zero phase
+            let corr = est.correction();
+            let vis = xc.visibility();
+            let resid = vis
+                .iter()
+                .zip(&corr.weights)
+                .map(|(v, w)| v * Complex::new(w[0], w[1]))
+                .sum::<Complex<f64>>();
+            assert!(resid.arg().abs() < 0.02);
+        }
+    }
+}